@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Instant;
+
+use rand::seq::index::sample;
+use rand::Rng;
+use starknet_api::hash::StarkFelt;
+
+use crate::block::Block;
+use crate::config::{BenchArgs, StorageLayout};
+use crate::db::{Database, DatabaseError};
+use crate::state_update::{StateDiff, StateUpdate, StorageDiff};
+
+/// Throughput and size of a database after a benchmark run.
+struct BenchResult {
+    blocks_per_second: f64,
+    reads_per_second: f64,
+    size_bytes: u64,
+}
+
+/// Apply the same kind of generated blocks to a new database of each storage
+/// layout, each block writing random slots among a fixed set of hot slots,
+/// then read random slots at random blocks.
+pub fn run(args: &BenchArgs) -> Result<(), String> {
+    if args.blocks == 0 || args.slots == 0 {
+        return Err("the benchmark needs at least one block and one slot".into());
+    }
+    if args.writes_per_block > args.slots {
+        return Err(format!(
+            "cannot write {} distinct slots per block out of {}",
+            args.writes_per_block, args.slots
+        ));
+    }
+    let dir = args.dir.clone().unwrap_or_else(std::env::temp_dir);
+
+    println!(
+        "{} blocks writing {} of {} slots, {} reads",
+        args.blocks, args.writes_per_block, args.slots, args.reads
+    );
+    println!(
+        "{:<8} {:>12} {:>12} {:>12}",
+        "layout", "blocks/s", "reads/s", "size (MiB)"
+    );
+    for storage_layout in [StorageLayout::History, StorageLayout::Flat] {
+        let path = dir.join(format!(
+            "explorer-bench-{:?}-{}",
+            storage_layout,
+            std::process::id()
+        ));
+        let result = bench_layout(&path, storage_layout, args);
+        let _ = std::fs::remove_dir_all(&path);
+        let result = result?;
+        println!(
+            "{:<8} {:>12.1} {:>12.1} {:>12.1}",
+            format!("{:?}", storage_layout),
+            result.blocks_per_second,
+            result.reads_per_second,
+            result.size_bytes as f64 / (1024.0 * 1024.0)
+        );
+    }
+    Ok(())
+}
+
+fn bench_layout(
+    path: &Path,
+    storage_layout: StorageLayout,
+    args: &BenchArgs,
+) -> Result<BenchResult, String> {
+    let db_error = |e: DatabaseError| format!("Database error: {e}");
+    let db = Database::new(
+        path.to_str().ok_or("invalid benchmark directory")?,
+        None,
+        storage_layout,
+    )
+    .map_err(db_error)?;
+    let contract = StarkFelt::from(1u64);
+    let mut rng = rand::thread_rng();
+
+    let start = Instant::now();
+    for block_number in 0..args.blocks {
+        let storage_diffs = sample(&mut rng, args.slots, args.writes_per_block)
+            .into_iter()
+            .map(|slot| StorageDiff {
+                key: StarkFelt::from(slot as u64),
+                value: StarkFelt::from(block_number + 1),
+            })
+            .collect();
+        let (block, state_update) = generated_block(block_number, contract, storage_diffs);
+        db.apply_block(block_number, &block, &state_update)
+            .map_err(db_error)?;
+    }
+    db.flush().map_err(db_error)?;
+    let blocks_per_second = args.blocks as f64 / start.elapsed().as_secs_f64();
+
+    let start = Instant::now();
+    for _ in 0..args.reads {
+        let key = StarkFelt::from(rng.gen_range(0..args.slots as u64));
+        db.get_key_at(contract, key, rng.gen_range(0..args.blocks))
+            .map_err(db_error)?;
+    }
+    let reads_per_second = args.reads as f64 / start.elapsed().as_secs_f64();
+
+    drop(db);
+    Ok(BenchResult {
+        blocks_per_second,
+        reads_per_second,
+        size_bytes: directory_size(path)?,
+    })
+}
+
+/// A block only writing the given slots of a contract.
+fn generated_block(
+    block_number: u64,
+    contract: StarkFelt,
+    storage_diffs: Vec<StorageDiff>,
+) -> (Block, StateUpdate) {
+    let block_hash = StarkFelt::from(block_number + 1);
+    let block = Block {
+        block_hash,
+        parent_block_hash: StarkFelt::from(block_number),
+        block_number,
+        timestamp: 0,
+        sequencer_address: None,
+        gas_price: None,
+        l1_gas_price: None,
+        starknet_version: None,
+        transactions: Vec::new(),
+        transaction_receipts: Vec::new(),
+    };
+    let state_update = StateUpdate {
+        block_hash,
+        new_root: StarkFelt::default(),
+        old_root: StarkFelt::default(),
+        state_diff: StateDiff {
+            storage_diffs: HashMap::from([(contract, storage_diffs)]),
+            deployed_contracts: Vec::new(),
+            old_declared_contracts: Vec::new(),
+            declared_classes: Vec::new(),
+            nonces: HashMap::new(),
+            replaced_classes: Vec::new(),
+            migrated_compiled_classes: Vec::new(),
+        },
+    };
+    (block, state_update)
+}
+
+fn directory_size(path: &Path) -> Result<u64, String> {
+    let entries =
+        std::fs::read_dir(path).map_err(|e| format!("Cannot read {}: {e}", path.display()))?;
+    let mut size = 0;
+    for entry in entries {
+        let metadata = entry
+            .and_then(|entry| entry.metadata())
+            .map_err(|e| format!("Cannot read {}: {e}", path.display()))?;
+        if metadata.is_file() {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
+}
@@ -0,0 +1,119 @@
+use serde::{Deserialize, Serialize};
+use starknet_api::hash::StarkFelt;
+
+use crate::state_update::StateUpdate;
+use crate::transaction::{Receipt, Transaction};
+
+/// A block, as returned by the feeder gateway.
+#[derive(Deserialize, Debug)]
+pub struct Block {
+    pub block_hash: StarkFelt,
+    pub parent_block_hash: StarkFelt,
+    pub block_number: u64,
+    pub timestamp: u64,
+    #[serde(default)]
+    pub sequencer_address: Option<StarkFelt>,
+    /// L1 gas price in wei, before Starknet v0.13.
+    #[serde(default)]
+    pub gas_price: Option<StarkFelt>,
+    #[serde(default)]
+    pub l1_gas_price: Option<GasPrice>,
+    #[serde(default)]
+    pub starknet_version: Option<String>,
+    pub transactions: Vec<Transaction>,
+    pub transaction_receipts: Vec<Receipt>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GasPrice {
+    pub price_in_wei: StarkFelt,
+    #[serde(default)]
+    pub price_in_fri: Option<StarkFelt>,
+}
+
+/// Header of a block applied to the database.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlockHeader {
+    pub block_number: u64,
+    pub block_hash: StarkFelt,
+    pub parent_block_hash: StarkFelt,
+    /// Unix timestamp (in seconds) of the block.
+    pub timestamp: u64,
+    pub sequencer_address: Option<StarkFelt>,
+    pub l1_gas_price_wei: Option<StarkFelt>,
+    pub l1_gas_price_fri: Option<StarkFelt>,
+    pub starknet_version: Option<String>,
+    /// Global state root before the block.
+    pub old_root: StarkFelt,
+    /// Global state root after the block.
+    pub new_root: StarkFelt,
+    pub storage_diffs: u64,
+    pub deployed_contracts: u64,
+    pub nonce_updates: u64,
+    /// Cairo 0 and Cairo 1 declarations.
+    pub declared_classes: u64,
+    pub replaced_classes: u64,
+    pub transactions: u64,
+    pub events: u64,
+}
+
+impl BlockHeader {
+    pub fn new(block_number: u64, block: &Block, state_update: &StateUpdate) -> Self {
+        let state_diff = &state_update.state_diff;
+        BlockHeader {
+            block_number,
+            block_hash: state_update.block_hash,
+            parent_block_hash: block.parent_block_hash,
+            timestamp: block.timestamp,
+            sequencer_address: block.sequencer_address,
+            l1_gas_price_wei: block
+                .l1_gas_price
+                .as_ref()
+                .map(|price| price.price_in_wei)
+                .or(block.gas_price),
+            l1_gas_price_fri: block
+                .l1_gas_price
+                .as_ref()
+                .and_then(|price| price.price_in_fri),
+            starknet_version: block.starknet_version.clone(),
+            old_root: state_update.old_root,
+            new_root: state_update.new_root,
+            storage_diffs: state_diff
+                .storage_diffs
+                .values()
+                .map(Vec::len)
+                .sum::<usize>() as u64,
+            deployed_contracts: state_diff.deployed_contracts.len() as u64,
+            nonce_updates: state_diff.nonces.len() as u64,
+            declared_classes: (state_diff.declared_classes.len()
+                + state_diff.old_declared_contracts.len()) as u64,
+            replaced_classes: state_diff.replaced_classes.len() as u64,
+            transactions: block.transactions.len() as u64,
+            events: block
+                .transaction_receipts
+                .iter()
+                .map(|receipt| receipt.events.len())
+                .sum::<usize>() as u64,
+        }
+    }
+}
+
+/// A chain reorganization detected and rolled back by the syncer.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Reorg {
+    /// Unix timestamp (in seconds) of the detection.
+    pub timestamp: u64,
+    /// Block whose parent did not match the database.
+    pub detected_at: u64,
+    /// Last block shared by the database and the canonical chain.
+    pub common_ancestor: u64,
+    /// Orphaned blocks removed from the database, as `(block number, block hash)`.
+    pub orphaned: Vec<(u64, StarkFelt)>,
+}
+
+impl Reorg {
+    /// Number of blocks rolled back.
+    pub fn depth(&self) -> usize {
+        self.orphaned.len()
+    }
+}
@@ -0,0 +1,59 @@
+use serde::{Deserialize, Serialize};
+use starknet_api::hash::StarkFelt;
+
+/// Everything a block changed in the state, with the values before the block.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ChangeSet {
+    pub storage: Vec<StorageChange>,
+    pub nonces: Vec<ContractChange>,
+    /// Deployments and class replacements.
+    pub class_hashes: Vec<ContractChange>,
+    /// Classes declared by the block.
+    pub declared_classes: Vec<StarkFelt>,
+    /// Compiled class hash changes of classes declared before the block.
+    pub compiled_class_hashes: Vec<CompiledClassHashChange>,
+}
+
+/// A storage slot written by a block.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct StorageChange {
+    pub contract: StarkFelt,
+    pub key: StarkFelt,
+    /// `None` if the slot was never written before.
+    pub previous: Option<StarkFelt>,
+    pub value: StarkFelt,
+}
+
+/// A nonce or class hash of a contract set by a block.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct ContractChange {
+    pub contract: StarkFelt,
+    /// `None` if the value was never set before.
+    pub previous: Option<StarkFelt>,
+    pub value: StarkFelt,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct CompiledClassHashChange {
+    pub class_hash: StarkFelt,
+    pub previous: Option<StarkFelt>,
+    pub value: StarkFelt,
+}
+
+impl ChangeSet {
+    /// Contracts whose nonce or class hash changed.
+    pub fn contracts(&self) -> impl Iterator<Item = StarkFelt> + '_ {
+        self.nonces
+            .iter()
+            .chain(&self.class_hashes)
+            .map(|change| change.contract)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.storage.is_empty()
+            && self.nonces.is_empty()
+            && self.class_hashes.is_empty()
+            && self.declared_classes.is_empty()
+            && self.compiled_class_hashes.is_empty()
+    }
+}
@@ -0,0 +1,123 @@
+use serde::{Deserialize, Serialize};
+use starknet_api::hash::StarkFelt;
+
+use crate::history::{History, HistoryError};
+
+/// A declared class.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Class {
+    declared_at: u64,
+    kind: ClassKind,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+enum ClassKind {
+    /// Cairo 0 classes are not compiled to CASM.
+    Cairo0,
+    Cairo1 {
+        compiled_class_hash: History<StarkFelt>,
+    },
+}
+
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClassError {
+    #[error("Cairo 0 classes have no compiled class hash")]
+    NotCompiled,
+    #[error(transparent)]
+    History(#[from] HistoryError),
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CairoVersion {
+    Cairo0,
+    Cairo1,
+}
+
+/// When and how a class was declared.
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct ClassDeclaration {
+    pub block_number: u64,
+    pub cairo_version: CairoVersion,
+}
+
+/// A contract using a class during a block range.
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct ClassUsage {
+    pub contract: StarkFelt,
+    /// Block the contract was deployed with or upgraded to the class.
+    pub from_block: u64,
+    /// Last block before the class was replaced, `None` if still in use.
+    pub to_block: Option<u64>,
+}
+
+impl Class {
+    pub fn new_cairo0(declared_at: u64) -> Self {
+        Class {
+            declared_at,
+            kind: ClassKind::Cairo0,
+        }
+    }
+
+    pub fn new_cairo1(declared_at: u64, compiled_class_hash: StarkFelt) -> Self {
+        let mut history = History::new();
+        // a new history accepts any index
+        let _ = history.push(declared_at, compiled_class_hash);
+        Class {
+            declared_at,
+            kind: ClassKind::Cairo1 {
+                compiled_class_hash: history,
+            },
+        }
+    }
+
+    pub fn declaration(&self) -> ClassDeclaration {
+        ClassDeclaration {
+            block_number: self.declared_at,
+            cairo_version: match self.kind {
+                ClassKind::Cairo0 => CairoVersion::Cairo0,
+                ClassKind::Cairo1 { .. } => CairoVersion::Cairo1,
+            },
+        }
+    }
+
+    /// Record a new compiled class hash of a Cairo 1 class.
+    /// Fails for Cairo 0 classes and for indexes not after the last change.
+    pub fn push_compiled_class_hash(
+        &mut self,
+        index: u64,
+        compiled_class_hash: StarkFelt,
+    ) -> Result<(), ClassError> {
+        match &mut self.kind {
+            ClassKind::Cairo0 => Err(ClassError::NotCompiled),
+            ClassKind::Cairo1 {
+                compiled_class_hash: history,
+            } => Ok(history.push(index, compiled_class_hash)?),
+        }
+    }
+
+    /// Compiled class hash at a given index, `None` for Cairo 0 classes and
+    /// before the declaration.
+    pub fn get_compiled_class_hash_at(&self, index: u64) -> Option<&StarkFelt> {
+        match &self.kind {
+            ClassKind::Cairo0 => None,
+            ClassKind::Cairo1 {
+                compiled_class_hash,
+            } => compiled_class_hash.get_at(index),
+        }
+    }
+
+    /// Revert the class to a given index.
+    /// The class must be removed instead if it was declared after `index`.
+    pub fn revert_to(&mut self, index: u64) {
+        if let ClassKind::Cairo1 {
+            compiled_class_hash,
+        } = &mut self.kind
+        {
+            compiled_class_hash.revert_to(index);
+        }
+    }
+
+    pub fn is_declared_at(&self, index: u64) -> bool {
+        self.declared_at <= index
+    }
+}
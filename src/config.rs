@@ -0,0 +1,618 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize};
+use starknet_api::hash::StarkFelt;
+
+/// Command-line interface of the explorer.
+///
+/// Every option given on the command line overrides the value read from the
+/// configuration file, which itself overrides the defaults.
+#[derive(Parser, Debug)]
+#[command(name = "explorer", version, about = "Starknet contract explorer")]
+pub struct Cli {
+    /// Path to a TOML configuration file
+    #[arg(short, long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Network to sync from
+    #[arg(long, global = true)]
+    pub network: Option<Network>,
+
+    /// Feeder gateway URL, overrides the network default
+    #[arg(long, global = true)]
+    pub gateway: Option<String>,
+
+    /// Path of the database directory
+    #[arg(long, global = true)]
+    pub db_path: Option<String>,
+
+    /// Layout of the storage histories, used when creating the database
+    #[arg(long, global = true, value_enum)]
+    pub storage_layout: Option<StorageLayout>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Sync a block range, then open the interactive explorer (default)
+    Sync(SyncArgs),
+    /// Keep syncing new blocks in the background while the interactive explorer is open
+    Follow(FollowArgs),
+    /// Open the interactive explorer without syncing
+    Explore,
+    /// Serve the database over the Starknet JSON-RPC and REST APIs
+    Serve(ServeArgs),
+    /// Print the value of a storage slot
+    Storage(StorageArgs),
+    /// Dump every non-zero storage slot of a contract
+    StorageDump(StorageDumpArgs),
+    /// List the known contracts with their deployment block and current state
+    Contracts(ContractsArgs),
+    /// List every contract that used a class, with the blocks it used it during
+    ClassUsage(ClassUsageArgs),
+    /// Print the nonce of a contract
+    Nonce(ContractArgs),
+    /// Print the class hash of a contract
+    ClassHash(ContractArgs),
+    /// Revert the database to a block
+    Revert(RevertArgs),
+    /// Print the state changes of a block, with the values they overwrote
+    Changes(ChangesArgs),
+    /// Print every change of a storage slot, nonce or class hash
+    #[command(subcommand)]
+    History(HistoryCommand),
+    /// Upgrade the database to the current schema version, in place
+    Migrate,
+    /// Compare the write and read performance of the storage layouts on
+    /// generated blocks
+    Bench(BenchArgs),
+}
+
+#[derive(Args, Debug, Default)]
+pub struct SyncArgs {
+    /// First block to sync
+    #[arg(long)]
+    pub start: Option<u64>,
+
+    /// Last block to sync (inclusive)
+    #[arg(long)]
+    pub end: Option<u64>,
+
+    #[command(flatten)]
+    pub fetch: FetchArgs,
+}
+
+#[derive(Args, Debug, Default)]
+pub struct FollowArgs {
+    /// First block to sync if the database is empty
+    #[arg(long)]
+    pub start: Option<u64>,
+
+    /// Delay between two polls of the latest block, in milliseconds
+    #[arg(long)]
+    pub poll_interval_ms: Option<u64>,
+
+    #[command(flatten)]
+    pub fetch: FetchArgs,
+}
+
+#[derive(Args, Debug, Default)]
+pub struct ServeArgs {
+    /// Address the server listens on
+    #[arg(long)]
+    pub address: Option<String>,
+
+    /// Keep syncing new blocks while serving
+    #[arg(long)]
+    pub follow: bool,
+
+    #[command(flatten)]
+    pub sync: FollowArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct StorageArgs {
+    /// Contract address
+    #[arg(value_parser = parse_felt)]
+    pub contract: StarkFelt,
+
+    /// Storage key
+    #[arg(value_parser = parse_felt)]
+    pub key: StarkFelt,
+
+    #[command(flatten)]
+    pub query: QueryArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct StorageDumpArgs {
+    /// Contract address
+    #[arg(value_parser = parse_felt)]
+    pub contract: StarkFelt,
+
+    /// First storage key to dump, to resume a partial dump
+    #[arg(long, value_parser = parse_felt)]
+    pub from_key: Option<StarkFelt>,
+
+    /// Maximum number of slots to dump
+    #[arg(long)]
+    pub limit: Option<usize>,
+
+    #[command(flatten)]
+    pub query: QueryArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct ContractsArgs {
+    /// Only contracts deployed at or after this block
+    #[arg(long)]
+    pub deployed_from: Option<u64>,
+
+    /// Only contracts deployed at or before this block
+    #[arg(long)]
+    pub deployed_to: Option<u64>,
+
+    /// Only contracts currently using this class hash
+    #[arg(long, value_parser = parse_felt)]
+    pub class_hash: Option<StarkFelt>,
+
+    /// First address to list, to resume a partial listing
+    #[arg(long, value_parser = parse_felt)]
+    pub from_address: Option<StarkFelt>,
+
+    /// Maximum number of contracts to list
+    #[arg(long)]
+    pub limit: Option<usize>,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct ClassUsageArgs {
+    /// Class hash
+    #[arg(value_parser = parse_felt)]
+    pub class_hash: StarkFelt,
+
+    /// Only contracts still using the class
+    #[arg(long)]
+    pub current: bool,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct ContractArgs {
+    /// Contract address
+    #[arg(value_parser = parse_felt)]
+    pub contract: StarkFelt,
+
+    #[command(flatten)]
+    pub query: QueryArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct QueryArgs {
+    /// Block to read the state at, the latest synced block by default
+    #[arg(long)]
+    pub block: Option<u64>,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct RevertArgs {
+    /// Block to revert to, kept in the database
+    #[arg(long)]
+    pub to: u64,
+
+    /// Revert without asking for confirmation
+    #[arg(long)]
+    pub yes: bool,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct ChangesArgs {
+    /// Block number
+    pub block: u64,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct BenchArgs {
+    /// Number of blocks applied
+    #[arg(long, default_value_t = 10_000)]
+    pub blocks: u64,
+
+    /// Number of distinct storage slots written
+    #[arg(long, default_value_t = 1_000)]
+    pub slots: usize,
+
+    /// Number of slots written by each block
+    #[arg(long, default_value_t = 20)]
+    pub writes_per_block: usize,
+
+    /// Number of random reads of a slot at a block
+    #[arg(long, default_value_t = 100_000)]
+    pub reads: usize,
+
+    /// Directory of the benchmark databases, the temporary directory by default
+    #[arg(long)]
+    pub dir: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum HistoryCommand {
+    /// Changes of a storage slot
+    Storage {
+        /// Contract address
+        #[arg(value_parser = parse_felt)]
+        contract: StarkFelt,
+
+        /// Storage key
+        #[arg(value_parser = parse_felt)]
+        key: StarkFelt,
+
+        #[command(flatten)]
+        range: HistoryRangeArgs,
+    },
+    /// Changes of the nonce of a contract
+    Nonce {
+        /// Contract address
+        #[arg(value_parser = parse_felt)]
+        contract: StarkFelt,
+
+        #[command(flatten)]
+        range: HistoryRangeArgs,
+    },
+    /// Class hashes of a contract: its deployment and class replacements
+    ClassHash {
+        /// Contract address
+        #[arg(value_parser = parse_felt)]
+        contract: StarkFelt,
+
+        #[command(flatten)]
+        range: HistoryRangeArgs,
+    },
+}
+
+#[derive(Args, Debug)]
+pub struct HistoryRangeArgs {
+    /// First block of the listed changes
+    #[arg(long)]
+    pub from: Option<u64>,
+
+    /// Last block of the listed changes (inclusive)
+    #[arg(long)]
+    pub to: Option<u64>,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct OutputArgs {
+    /// Output format
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Json,
+    Text,
+    Csv,
+}
+
+#[derive(Args, Debug, Default)]
+pub struct FetchArgs {
+    /// Number of blocks fetched simultaneously
+    #[arg(long)]
+    pub concurrency: Option<usize>,
+
+    /// Number of fetched blocks waiting to be applied before fetching is paused
+    #[arg(long)]
+    pub pipeline_buffer: Option<usize>,
+
+    /// Number of retries of a failed request before giving up
+    #[arg(long)]
+    pub max_attempts: Option<u32>,
+}
+
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Network {
+    #[default]
+    Mainnet,
+    Sepolia,
+    /// A local node or devnet exposing a feeder gateway
+    Local,
+}
+
+/// How the changes of storage slots are stored, chosen when the database is
+/// created.
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageLayout {
+    /// One entry per slot holding its whole history, rewritten on each change
+    #[default]
+    History,
+    /// One entry per change, keyed by `contract || key || block`
+    Flat,
+}
+
+impl Network {
+    /// Default feeder gateway URL of the network.
+    pub fn feeder_gateway(&self) -> &'static str {
+        match self {
+            Network::Mainnet => "https://alpha-mainnet.starknet.io/feeder_gateway",
+            Network::Sepolia => "https://alpha-sepolia.starknet.io/feeder_gateway",
+            Network::Local => "http://127.0.0.1:3000/feeder_gateway",
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ConfigError {
+    #[error("Cannot read config file {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("Invalid config file: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("Invalid config: {0}")]
+    Invalid(String),
+}
+
+/// Configuration of the explorer, as read from the TOML config file.
+///
+/// ```toml
+/// network = "sepolia"
+/// db_path = "db-sepolia"
+/// storage_layout = "flat"
+///
+/// [sync]
+/// start_block = 0
+/// end_block = 10000
+/// concurrency = 20
+/// max_attempts = 20
+/// poll_interval_ms = 2000
+/// max_reorg_depth = 64
+/// pipeline_buffer = 64
+/// metrics_interval_secs = 10
+///
+/// [client]
+/// requests_per_second = 20.0
+/// burst = 20
+/// retry_base_delay_ms = 500
+/// retry_max_delay_ms = 60000
+/// timeout_secs = 30
+///
+/// [server]
+/// address = "127.0.0.1:9545"
+/// ```
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Network to sync from, mainnet if not set. A database is only checked
+    /// against it when it is set or when syncing.
+    pub network: Option<Network>,
+    pub gateway: Option<String>,
+    pub db_path: String,
+    /// Layout of a new database, an existing one keeps its own.
+    pub storage_layout: Option<StorageLayout>,
+    pub sync: SyncConfig,
+    pub client: GatewayConfig,
+    pub server: ServerConfig,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SyncConfig {
+    pub start_block: u64,
+    pub end_block: u64,
+    pub concurrency: usize,
+    pub max_attempts: u32,
+    /// Delay between two polls of the latest block in follow mode
+    pub poll_interval_ms: u64,
+    /// Deepest chain reorganization rolled back automatically
+    pub max_reorg_depth: u64,
+    /// Fetched blocks waiting to be applied before fetching is paused
+    pub pipeline_buffer: usize,
+    /// Delay between two throughput reports
+    pub metrics_interval_secs: u64,
+}
+
+/// Rate limiting and retry policy of the feeder gateway client.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct GatewayConfig {
+    /// Sustained request rate, lowered automatically when throttled
+    pub requests_per_second: f64,
+    /// Requests that can be sent at once after an idle period
+    pub burst: u32,
+    /// Delay before the first retry, doubled on each attempt
+    pub retry_base_delay_ms: u64,
+    /// Longest delay between two attempts, including `Retry-After` delays
+    pub retry_max_delay_ms: u64,
+    pub timeout_secs: u64,
+}
+
+/// JSON-RPC server.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub address: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            network: None,
+            gateway: None,
+            db_path: "db".to_string(),
+            storage_layout: None,
+            sync: SyncConfig::default(),
+            client: GatewayConfig::default(),
+            server: ServerConfig::default(),
+        }
+    }
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        SyncConfig {
+            start_block: 0,
+            end_block: 10_000,
+            concurrency: 20,
+            max_attempts: 20,
+            poll_interval_ms: 2_000,
+            max_reorg_depth: 64,
+            pipeline_buffer: 64,
+            metrics_interval_secs: 10,
+        }
+    }
+}
+
+impl Default for GatewayConfig {
+    fn default() -> Self {
+        GatewayConfig {
+            requests_per_second: 20.0,
+            burst: 20,
+            retry_base_delay_ms: 500,
+            retry_max_delay_ms: 60_000,
+            timeout_secs: 30,
+        }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            address: "127.0.0.1:9545".to_string(),
+        }
+    }
+}
+
+impl Config {
+    /// Build the configuration from the config file given on the command line
+    /// (if any) and the command-line overrides.
+    pub fn load(cli: &Cli) -> Result<Self, ConfigError> {
+        let mut config = match &cli.config {
+            Some(path) => {
+                let content =
+                    std::fs::read_to_string(path).map_err(|e| ConfigError::Io(path.clone(), e))?;
+                toml::from_str(&content)?
+            }
+            None => Config::default(),
+        };
+
+        if let Some(network) = cli.network {
+            config.network = Some(network);
+        }
+        if let Some(gateway) = &cli.gateway {
+            config.gateway = Some(gateway.clone());
+        }
+        if let Some(db_path) = &cli.db_path {
+            config.db_path = db_path.clone();
+        }
+        if let Some(storage_layout) = cli.storage_layout {
+            config.storage_layout = Some(storage_layout);
+        }
+        match &cli.command {
+            Some(Command::Sync(args)) => config.sync.apply_sync(args),
+            Some(Command::Follow(args)) => config.sync.apply_follow(args),
+            Some(Command::Serve(args)) => {
+                config.sync.apply_follow(&args.sync);
+                if let Some(address) = &args.address {
+                    config.server.address = address.clone();
+                }
+            }
+            _ => {}
+        }
+
+        config.validate(cli.command.as_ref())?;
+        Ok(config)
+    }
+
+    /// Feeder gateway URL to sync from.
+    pub fn feeder_gateway(&self) -> &str {
+        match &self.gateway {
+            Some(gateway) => gateway.trim_end_matches('/'),
+            None => self.network.unwrap_or_default().feeder_gateway(),
+        }
+    }
+
+    fn validate(&self, command: Option<&Command>) -> Result<(), ConfigError> {
+        // the end block only bounds a sync, following has no end
+        let syncs_range = matches!(command, Some(Command::Sync(_)) | None);
+        if syncs_range && self.sync.start_block > self.sync.end_block {
+            return Err(ConfigError::Invalid(format!(
+                "start block {} is after end block {}",
+                self.sync.start_block, self.sync.end_block
+            )));
+        }
+        if self.client.requests_per_second.is_nan() || self.client.requests_per_second <= 0.0 {
+            return Err(ConfigError::Invalid(
+                "requests per second must be positive".into(),
+            ));
+        }
+        if self.sync.pipeline_buffer == 0 {
+            return Err(ConfigError::Invalid(
+                "pipeline buffer must be at least 1".into(),
+            ));
+        }
+        if self.sync.concurrency == 0 {
+            return Err(ConfigError::Invalid(
+                "concurrency must be at least 1".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn parse_felt(value: &str) -> Result<StarkFelt, String> {
+    StarkFelt::try_from(value).map_err(|e| format!("invalid felt {value}: {e}"))
+}
+
+impl SyncConfig {
+    fn apply_sync(&mut self, args: &SyncArgs) {
+        if let Some(start) = args.start {
+            self.start_block = start;
+        }
+        if let Some(end) = args.end {
+            self.end_block = end;
+        }
+        self.apply_fetch(&args.fetch);
+    }
+
+    fn apply_follow(&mut self, args: &FollowArgs) {
+        if let Some(start) = args.start {
+            self.start_block = start;
+        }
+        if let Some(poll_interval_ms) = args.poll_interval_ms {
+            self.poll_interval_ms = poll_interval_ms;
+        }
+        self.apply_fetch(&args.fetch);
+    }
+
+    fn apply_fetch(&mut self, args: &FetchArgs) {
+        if let Some(concurrency) = args.concurrency {
+            self.concurrency = concurrency;
+        }
+        if let Some(pipeline_buffer) = args.pipeline_buffer {
+            self.pipeline_buffer = pipeline_buffer;
+        }
+        if let Some(max_attempts) = args.max_attempts {
+            self.max_attempts = max_attempts;
+        }
+    }
+}
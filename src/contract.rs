@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 use starknet_api::hash::StarkFelt;
 
-use crate::history::History;
+use crate::history::{History, HistoryError};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Contract {
@@ -9,6 +9,52 @@ pub struct Contract {
     nonce: History<StarkFelt>,
 }
 
+/// Current state of a contract, as listed by `Database::list_contracts`.
+#[derive(Serialize, Debug, Clone)]
+pub struct ContractSummary {
+    pub address: StarkFelt,
+    /// Block of the first class hash, `None` if the deployment was not synced.
+    pub deployed_at: Option<u64>,
+    pub class_hash: Option<StarkFelt>,
+    pub nonce: Option<StarkFelt>,
+    /// Number of class hash changes after the deployment.
+    pub class_replacements: usize,
+}
+
+/// Filter of `Database::list_contracts`, every criterion given must match.
+#[derive(Debug, Clone, Default)]
+pub struct ContractFilter {
+    /// First deployment block, inclusive.
+    pub deployed_from: Option<u64>,
+    /// Last deployment block, inclusive.
+    pub deployed_to: Option<u64>,
+    /// Current class hash.
+    pub class_hash: Option<StarkFelt>,
+}
+
+impl ContractFilter {
+    pub fn matches(&self, contract: &ContractSummary) -> bool {
+        let deployed_in_range = match contract.deployed_at {
+            Some(block_number) => {
+                self.deployed_from.is_none_or(|from| block_number >= from)
+                    && self.deployed_to.is_none_or(|to| block_number <= to)
+            }
+            None => self.deployed_from.is_none() && self.deployed_to.is_none(),
+        };
+        let uses_class = self
+            .class_hash
+            .is_none_or(|class_hash| contract.class_hash == Some(class_hash));
+        deployed_in_range && uses_class
+    }
+}
+
+/// A page of contracts, with the address to list the next one from if there are more.
+#[derive(Serialize, Debug, Clone)]
+pub struct ContractsPage {
+    pub contracts: Vec<ContractSummary>,
+    pub next_address: Option<StarkFelt>,
+}
+
 impl Contract {
     pub fn new() -> Self {
         Contract {
@@ -17,7 +63,11 @@ impl Contract {
         }
     }
 
-    pub fn push_class_hash(&mut self, index: u64, class_hash: StarkFelt) -> Result<(), ()> {
+    pub fn push_class_hash(
+        &mut self,
+        index: u64,
+        class_hash: StarkFelt,
+    ) -> Result<(), HistoryError> {
         self.class_hash.push(index, class_hash)
     }
 
@@ -29,7 +79,11 @@ impl Contract {
         self.class_hash.get_at(index)
     }
 
-    pub fn push_nonce(&mut self, index: u64, nonce: StarkFelt) -> Result<(), ()> {
+    pub fn class_hash_history(&self) -> &History<StarkFelt> {
+        &self.class_hash
+    }
+
+    pub fn push_nonce(&mut self, index: u64, nonce: StarkFelt) -> Result<(), HistoryError> {
         self.nonce.push(index, nonce)
     }
 
@@ -41,6 +95,20 @@ impl Contract {
         self.nonce.get_at(index)
     }
 
+    pub fn nonce_history(&self) -> &History<StarkFelt> {
+        &self.nonce
+    }
+
+    pub fn summary(&self, address: StarkFelt) -> ContractSummary {
+        ContractSummary {
+            address,
+            deployed_at: self.class_hash.first().map(|(index, _)| index),
+            class_hash: self.get_class_hash().copied(),
+            nonce: self.get_nonce().copied(),
+            class_replacements: self.class_hash.len().saturating_sub(1),
+        }
+    }
+
     pub fn revert_to(&mut self, index: u64) {
         self.class_hash.revert_to(index);
         self.nonce.revert_to(index);
@@ -1,32 +1,448 @@
-use rocksdb::{ColumnFamilyDescriptor, DBCompressionType, Options, DB};
+use std::collections::hash_map;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, MutexGuard};
+
+use rocksdb::{ColumnFamilyDescriptor, DBCompressionType, Options, WriteBatch, DB};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 use starknet_api::hash::StarkFelt;
 
-use crate::contract::Contract;
-use crate::history::History;
+use crate::block::{Block, BlockHeader, Reorg};
+use crate::change_set::{ChangeSet, CompiledClassHashChange, ContractChange, StorageChange};
+use crate::class::{Class, ClassDeclaration, ClassError, ClassUsage};
+use crate::config::{Network, StorageLayout};
+use crate::contract::{Contract, ContractFilter, ContractsPage};
+use crate::event::{EmittedEvent, EventFilter, EventPosition, EventsPage, StoredEvent};
+use crate::history::{History, HistoryError};
+use crate::state_update::{DeclaredClass, StateDiff, StateUpdate, StorageDiff};
+use crate::transaction::{NonceMismatch, Receipt, Transaction, TransactionLocation};
 
 pub struct Database {
     db: DB,
     path: String,
+    /// Serializes the read-modify-write updates of histories: two concurrent
+    /// updates of the same blob would otherwise overwrite each other.
+    write_lock: Mutex<()>,
+    metadata: DatabaseMetadata,
+}
+
+const SYNC_CURSOR_KEY: &[u8] = b"sync_cursor";
+const METADATA_KEY: &[u8] = b"metadata";
+/// Layout of the storage before the metadata record was added.
+const STORAGE_LAYOUT_KEY: &[u8] = b"storage_layout";
+
+/// Version of the on-disk format, increased on every incompatible change of
+/// the encoded keys or values.
+///
+/// - 0: no metadata record.
+/// - 1: metadata record, change set of every block, class usage index.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Writes of a migration are committed in batches of this many operations.
+const MIGRATION_BATCH_SIZE: usize = 10_000;
+
+/// A raw `(key, value)` pair of a column.
+type Entry = (Box<[u8]>, Box<[u8]>);
+
+/// Storage slots of a contract, as `(key, value)`.
+type Slots<'a> = Box<dyn Iterator<Item = Result<(StarkFelt, StarkFelt), DatabaseError>> + 'a>;
+
+/// Last block fully applied to the database.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct SyncCursor {
+    pub block_number: u64,
+    /// `None` when the cursor was moved back by a revert and the hash of the
+    /// block is not known.
+    pub block_hash: Option<StarkFelt>,
+}
+
+/// Description of a database, checked when it is opened.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DatabaseMetadata {
+    pub schema_version: u32,
+    /// Network, and so chain id, the database was synced from.
+    pub network: Network,
+    pub storage_layout: StorageLayout,
+    /// Version of the explorer that created the database.
+    pub created_by: String,
+    /// Creation time, or time of the migration from a database without
+    /// metadata, in seconds since the Unix epoch.
+    pub created_at: u64,
+}
+
+impl DatabaseMetadata {
+    fn new(network: Network, storage_layout: StorageLayout) -> Self {
+        DatabaseMetadata {
+            schema_version: SCHEMA_VERSION,
+            network,
+            storage_layout,
+            created_by: env!("CARGO_PKG_VERSION").to_string(),
+            created_at: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+        }
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
 pub enum DatabaseError {
     #[error("Column not found: {0}")]
     ColumnNotFound(String),
-    #[error("Decode error")]
-    DecodeError,
-    #[error("Encode error")]
-    EncodeError,
-    #[error("History error")]
-    HistoryError,
-    #[error("Iterator error")]
-    IteratorError,
-    #[error("RocksDB error: {0}")]
-    RocksDBError(rocksdb::Error),
+    #[error("Cannot decode the value of {} in column {cf}: {source}", hex(.key))]
+    DecodeError {
+        cf: String,
+        key: Vec<u8>,
+        #[source]
+        source: bincode::Error,
+    },
+    #[error("Cannot encode the value of {} in column {cf}: {source}", hex(.key))]
+    EncodeError {
+        cf: String,
+        key: Vec<u8>,
+        #[source]
+        source: bincode::Error,
+    },
+    /// A key, or a value stored as raw bytes, of an unexpected length.
+    #[error("Invalid entry {} in column {cf}", hex(.key))]
+    InvalidEntry { cf: String, key: Vec<u8> },
+    #[error("Cannot update the history of {} in column {cf}: {source}", hex(.key))]
+    HistoryError {
+        cf: String,
+        key: Vec<u8>,
+        #[source]
+        source: HistoryError,
+    },
+    #[error("Block {block_number} does not follow the last applied block {last}")]
+    NonContiguousBlock { block_number: u64, last: u64 },
+    #[error("Cannot update the class {class_hash}: {source}")]
+    ClassError {
+        class_hash: StarkFelt,
+        #[source]
+        source: ClassError,
+    },
+    #[error("Cannot iterate over column {cf}: {source}")]
+    IteratorError {
+        cf: String,
+        #[source]
+        source: rocksdb::Error,
+    },
+    #[error("RocksDB error on {} in column {cf}: {source}", hex(.key))]
+    RocksDBError {
+        cf: String,
+        key: Vec<u8>,
+        #[source]
+        source: rocksdb::Error,
+    },
+    #[error("Cannot write {operation}: {source}")]
+    WriteError {
+        operation: String,
+        #[source]
+        source: rocksdb::Error,
+    },
+    #[error("RocksDB error on the database at {path}: {source}")]
+    PathError {
+        path: String,
+        #[source]
+        source: rocksdb::Error,
+    },
+    #[error("Database schema version {found} is not supported, this explorer reads up to version {supported}")]
+    UnsupportedSchema { found: u32, supported: u32 },
+    #[error("No database at {0}")]
+    NotFound(String),
+    #[error("Database schema version {0} is outdated, upgrade it with `explorer migrate`")]
+    OutdatedSchema(u32),
+    #[error("The network of a database written before schema versions were recorded is unknown, give it with `--network`")]
+    NetworkRequired,
+    #[error("Database was synced from {database:?}, not {requested:?}")]
+    NetworkMismatch {
+        database: Network,
+        requested: Network,
+    },
 }
 
 impl Database {
-    pub fn new(path: &str) -> Result<Self, DatabaseError> {
+    /// Open the database at `path`, creating it for `network` (mainnet if
+    /// `None`) with `storage_layout` if it does not exist.
+    ///
+    /// Databases of another schema version are refused, older ones can be
+    /// upgraded with `Database::migrate`. Databases of another network are
+    /// refused only when `network` is given.
+    pub fn new(
+        path: &str,
+        network: Option<Network>,
+        storage_layout: StorageLayout,
+    ) -> Result<Self, DatabaseError> {
+        Database::load(path, network, Some(storage_layout))
+    }
+
+    /// Open the database at `path` like `Database::new`, but fail, without
+    /// creating it, if there is no database at `path`.
+    pub fn open_existing(path: &str, network: Option<Network>) -> Result<Self, DatabaseError> {
+        if !std::path::Path::new(path).exists() {
+            return Err(DatabaseError::NotFound(path.to_string()));
+        }
+        Database::load(path, network, None)
+    }
+
+    /// Open and check the database at `path`, creating it with
+    /// `storage_layout` if it is empty and one is given.
+    fn load(
+        path: &str,
+        network: Option<Network>,
+        storage_layout: Option<StorageLayout>,
+    ) -> Result<Self, DatabaseError> {
+        let mut database = Database::open(path)?;
+
+        match database.get("meta", METADATA_KEY)? {
+            Some(encoded) => {
+                let metadata: DatabaseMetadata = decode("meta", METADATA_KEY, &encoded)?;
+                if metadata.schema_version > SCHEMA_VERSION {
+                    return Err(DatabaseError::UnsupportedSchema {
+                        found: metadata.schema_version,
+                        supported: SCHEMA_VERSION,
+                    });
+                }
+                if metadata.schema_version < SCHEMA_VERSION {
+                    return Err(DatabaseError::OutdatedSchema(metadata.schema_version));
+                }
+                if let Some(network) = network.filter(|network| *network != metadata.network) {
+                    return Err(DatabaseError::NetworkMismatch {
+                        database: metadata.network,
+                        requested: network,
+                    });
+                }
+                database.metadata = metadata;
+            }
+            None if database.is_empty()? => {
+                let Some(storage_layout) = storage_layout else {
+                    return Err(DatabaseError::NotFound(path.to_string()));
+                };
+                database.metadata =
+                    DatabaseMetadata::new(network.unwrap_or_default(), storage_layout);
+                database.write_metadata()?;
+            }
+            // written before schema versions were recorded
+            None => return Err(DatabaseError::OutdatedSchema(0)),
+        }
+        Ok(database)
+    }
+
+    /// Upgrade the database at `path` to the current schema version, in place.
+    /// A database written before schema versions were recorded is assigned
+    /// to `network`, required as it cannot be changed afterwards.
+    ///
+    /// Returns the schema version the database was upgraded from. Fails,
+    /// without creating it, if there is no database at `path`.
+    pub fn migrate(path: &str, network: Option<Network>) -> Result<u32, DatabaseError> {
+        if !std::path::Path::new(path).exists() {
+            return Err(DatabaseError::NotFound(path.to_string()));
+        }
+        let mut database = Database::open(path)?;
+
+        match database.get("meta", METADATA_KEY)? {
+            Some(encoded) => database.metadata = decode("meta", METADATA_KEY, &encoded)?,
+            None if database.is_empty()? => return Err(DatabaseError::NotFound(path.to_string())),
+            None => {}
+        }
+        let from_version = database.metadata.schema_version;
+        if from_version > SCHEMA_VERSION {
+            return Err(DatabaseError::UnsupportedSchema {
+                found: from_version,
+                supported: SCHEMA_VERSION,
+            });
+        }
+        if from_version < 1 {
+            database.migrate_to_v1(network.ok_or(DatabaseError::NetworkRequired)?)?;
+        }
+        Ok(from_version)
+    }
+
+    /// Schema version 1: build the class usage index and the change sets of
+    /// the blocks applied before they were recorded, then record the metadata.
+    fn migrate_to_v1(&mut self, network: Network) -> Result<(), DatabaseError> {
+        let mut batch = WriteBatch::default();
+
+        let cf = self.cf("class_usage")?;
+        for item in self.iter("contract")? {
+            let (key, encoded) = item?;
+            let address = decode_felt("contract", &key, &key)?;
+            let contract: Contract = decode("contract", &key, &encoded)?;
+            let class_hashes: Vec<_> = contract.class_hash_history().iter().collect();
+            for (i, (from_block, class_hash)) in class_hashes.iter().enumerate() {
+                // used until the block before the next class
+                let to_block = class_hashes.get(i + 1).map(|(next, _)| next - 1);
+                let db_key = class_usage_key(class_hash, &address, *from_block);
+                let encoded = encode("class_usage", &db_key, &to_block)?;
+                batch.put_cf(cf, db_key, encoded);
+            }
+            self.write_if_full(&mut batch)?;
+        }
+
+        let cf = self.cf("change_set")?;
+        for item in self.iter("state_diff")? {
+            let (key, encoded) = item?;
+            if self.get("change_set", &key)?.is_some() {
+                continue;
+            }
+            let state_diff: StateDiff = decode("state_diff", &key, &encoded)?;
+            let changes =
+                self.rebuild_change_set(decode_block("state_diff", &key, &key)?, &state_diff)?;
+            let encoded = encode("change_set", &key, &changes)?;
+            batch.put_cf(cf, key, encoded);
+            self.write_if_full(&mut batch)?;
+        }
+
+        // written before the sync cursor was recorded, by a sync going
+        // contiguously from its start block: resume after the last change
+        if self.get_sync_cursor()?.is_none() {
+            if let Some(block_number) = self.last_changed_block()? {
+                let cursor = SyncCursor {
+                    block_number,
+                    block_hash: self
+                        .get_block_header(block_number)?
+                        .map(|header| header.block_hash),
+                };
+                let encoded = encode("meta", SYNC_CURSOR_KEY, &cursor)?;
+                batch.put_cf(self.cf("meta")?, SYNC_CURSOR_KEY, encoded);
+            }
+        }
+
+        // last, so an interrupted migration is run again
+        self.metadata = DatabaseMetadata::new(network, self.storage_layout());
+        let encoded = encode("meta", METADATA_KEY, &self.metadata)?;
+        let cf = self.cf("meta")?;
+        batch.put_cf(cf, METADATA_KEY, encoded);
+        batch.delete_cf(cf, STORAGE_LAYOUT_KEY);
+        self.write(batch, "the migration to schema version 1")
+    }
+
+    /// Highest block found in the histories and headers, `None` if nothing
+    /// was applied.
+    fn last_changed_block(&self) -> Result<Option<u64>, DatabaseError> {
+        let mut last = None;
+        for item in self.iter("contract")? {
+            let (key, encoded) = item?;
+            let contract: Contract = decode("contract", &key, &encoded)?;
+            for history in [contract.class_hash_history(), contract.nonce_history()] {
+                last = last.max(history.last().map(|(index, _)| index));
+            }
+        }
+        for item in self.iter("key")? {
+            let (key, encoded) = item?;
+            let history: History<StarkFelt> = decode("key", &key, &encoded)?;
+            last = last.max(history.last().map(|(index, _)| index));
+        }
+        for item in self.iter("storage")? {
+            let (change_key, _) = item?;
+            let block = change_key.get(64..).unwrap_or_default();
+            last = last.max(Some(decode_block("storage", &change_key, block)?));
+        }
+        if let Some(item) = self
+            .db
+            .iterator_cf(self.cf("block")?, rocksdb::IteratorMode::End)
+            .next()
+        {
+            let (key, _) = item.map_err(|source| iterator_error("block", source))?;
+            last = last.max(Some(decode_block("block", &key, &key)?));
+        }
+        Ok(last)
+    }
+
+    /// Write the pending operations of a migration once there are enough of them.
+    fn write_if_full(&self, batch: &mut WriteBatch) -> Result<(), DatabaseError> {
+        if batch.len() >= MIGRATION_BATCH_SIZE {
+            self.write(std::mem::take(batch), "the migration")?;
+        }
+        Ok(())
+    }
+
+    /// Change set of an applied block, with the previous values read from the
+    /// histories at the block before it.
+    fn rebuild_change_set(
+        &self,
+        block_number: u64,
+        state_diff: &StateDiff,
+    ) -> Result<ChangeSet, DatabaseError> {
+        let before = block_number.checked_sub(1);
+        let mut changes = ChangeSet::default();
+
+        for (contract, storage_diffs) in &state_diff.storage_diffs {
+            for StorageDiff { key, value } in storage_diffs {
+                let previous = before
+                    .map(|before| self.get_key_at(*contract, *key, before))
+                    .transpose()?
+                    .flatten();
+                changes.storage.push(StorageChange {
+                    contract: *contract,
+                    key: *key,
+                    previous,
+                    value: *value,
+                });
+            }
+        }
+        for deployed_contract in state_diff
+            .deployed_contracts
+            .iter()
+            .chain(&state_diff.replaced_classes)
+        {
+            let previous = before
+                .map(|before| self.get_class_hash_at(deployed_contract.address, before))
+                .transpose()?
+                .flatten();
+            changes.class_hashes.push(ContractChange {
+                contract: deployed_contract.address,
+                previous,
+                value: deployed_contract.class_hash,
+            });
+        }
+        for (contract, nonce) in &state_diff.nonces {
+            let previous = before
+                .map(|before| self.get_nonce_at(*contract, before))
+                .transpose()?
+                .flatten();
+            changes.nonces.push(ContractChange {
+                contract: *contract,
+                previous,
+                value: *nonce,
+            });
+        }
+
+        let declared_here = |class: &Class| class.declaration().block_number == block_number;
+        for class_hash in &state_diff.old_declared_contracts {
+            if self
+                .get_class(*class_hash)?
+                .is_some_and(|class| declared_here(&class))
+            {
+                changes.declared_classes.push(*class_hash);
+            }
+        }
+        for DeclaredClass {
+            class_hash,
+            compiled_class_hash,
+        } in state_diff
+            .declared_classes
+            .iter()
+            .chain(&state_diff.migrated_compiled_classes)
+        {
+            match self.get_class(*class_hash)? {
+                Some(class) if declared_here(&class) => changes.declared_classes.push(*class_hash),
+                Some(class) => changes.compiled_class_hashes.push(CompiledClassHashChange {
+                    class_hash: *class_hash,
+                    previous: before
+                        .and_then(|before| class.get_compiled_class_hash_at(before))
+                        .copied(),
+                    value: *compiled_class_hash,
+                }),
+                None => {}
+            }
+        }
+        Ok(changes)
+    }
+
+    /// Open the column families, with the metadata of a database written
+    /// before schema versions were recorded.
+    fn open(path: &str) -> Result<Self, DatabaseError> {
         let mut db_opts = Options::default();
         db_opts.set_compression_type(DBCompressionType::Zstd);
         db_opts.create_if_missing(true);
@@ -34,177 +450,435 @@ impl Database {
 
         let cf_opts = Options::default();
         let cf1 = ColumnFamilyDescriptor::new("contract", cf_opts.clone());
-        let cf2 = ColumnFamilyDescriptor::new("key", cf_opts);
+        let cf2 = ColumnFamilyDescriptor::new("key", cf_opts.clone());
+        let cf3 = ColumnFamilyDescriptor::new("meta", cf_opts.clone());
+        let cf4 = ColumnFamilyDescriptor::new("block", cf_opts.clone());
+        let cf5 = ColumnFamilyDescriptor::new("reorg", cf_opts.clone());
+        let cf6 = ColumnFamilyDescriptor::new("class", cf_opts.clone());
+        let cf7 = ColumnFamilyDescriptor::new("block_hash", cf_opts.clone());
+        let cf8 = ColumnFamilyDescriptor::new("transaction", cf_opts.clone());
+        let cf9 = ColumnFamilyDescriptor::new("receipt", cf_opts.clone());
+        let cf10 = ColumnFamilyDescriptor::new("event", cf_opts.clone());
+        let cf11 = ColumnFamilyDescriptor::new("event_address", cf_opts.clone());
+        let cf12 = ColumnFamilyDescriptor::new("event_key", cf_opts.clone());
+        let cf13 = ColumnFamilyDescriptor::new("transaction_hash", cf_opts.clone());
+        let cf14 = ColumnFamilyDescriptor::new("account_transaction", cf_opts.clone());
+        let cf15 = ColumnFamilyDescriptor::new("state_diff", cf_opts.clone());
+        let cf16 = ColumnFamilyDescriptor::new("class_usage", cf_opts.clone());
+        let cf17 = ColumnFamilyDescriptor::new("change_set", cf_opts.clone());
+        let cf18 = ColumnFamilyDescriptor::new("storage", cf_opts);
 
-        let db = DB::open_cf_descriptors(&db_opts, path, vec![cf1, cf2])
-            .map_err(|e| DatabaseError::RocksDBError(e))?;
+        let db = DB::open_cf_descriptors(
+            &db_opts,
+            path,
+            vec![
+                cf1, cf2, cf3, cf4, cf5, cf6, cf7, cf8, cf9, cf10, cf11, cf12, cf13, cf14, cf15,
+                cf16, cf17, cf18,
+            ],
+        )
+        .map_err(|source| path_error(path, source))?;
 
-        Ok(Database {
+        let mut database = Database {
             db,
             path: path.to_string(),
-        })
+            write_lock: Mutex::new(()),
+            metadata: DatabaseMetadata {
+                schema_version: 0,
+                network: Network::default(),
+                // the only layout before the flat one was added
+                storage_layout: StorageLayout::History,
+                created_by: String::new(),
+                created_at: 0,
+            },
+        };
+        if let Some(encoded) = database.get("meta", STORAGE_LAYOUT_KEY)? {
+            database.metadata.storage_layout = decode("meta", STORAGE_LAYOUT_KEY, &encoded)?;
+        }
+        Ok(database)
     }
 
-    fn insert(&self, cf: &str, key: &[u8], value: &[u8]) -> Result<(), DatabaseError> {
-        let cf = self
-            .db
-            .cf_handle(cf)
-            .ok_or(DatabaseError::ColumnNotFound(cf.to_string()))?;
+    /// Whether nothing was ever written to the database.
+    fn is_empty(&self) -> Result<bool, DatabaseError> {
+        for cf in ["meta", "key", "storage", "contract", "block"] {
+            if self.iter(cf)?.next().is_some() {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn write_metadata(&self) -> Result<(), DatabaseError> {
+        let encoded = encode("meta", METADATA_KEY, &self.metadata)?;
+        self.insert("meta", METADATA_KEY, &encoded)
+    }
+
+    /// Description of the database, written when it was created.
+    pub fn metadata(&self) -> &DatabaseMetadata {
+        &self.metadata
+    }
+
+    /// Layout the storage histories were created with.
+    pub fn storage_layout(&self) -> StorageLayout {
+        self.metadata.storage_layout
+    }
+
+    fn lock_writes(&self) -> MutexGuard<'_, ()> {
+        // a panic while holding the lock cannot leave a partial write behind
+        self.write_lock
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
 
+    /// Write `batch` atomically, `operation` naming it in errors.
+    fn write(&self, batch: WriteBatch, operation: &str) -> Result<(), DatabaseError> {
         self.db
-            .put_cf(cf, key, value)
-            .map_err(|e| DatabaseError::RocksDBError(e))
+            .write(batch)
+            .map_err(|source| DatabaseError::WriteError {
+                operation: operation.to_string(),
+                source,
+            })
     }
 
-    fn get(&self, cf: &str, key: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError> {
-        let cf = self
-            .db
+    fn cf(&self, cf: &str) -> Result<&rocksdb::ColumnFamily, DatabaseError> {
+        self.db
             .cf_handle(cf)
-            .ok_or(DatabaseError::ColumnNotFound(cf.to_string()))?;
+            .ok_or(DatabaseError::ColumnNotFound(cf.to_string()))
+    }
+
+    fn insert(&self, cf: &str, key: &[u8], value: &[u8]) -> Result<(), DatabaseError> {
+        let cf_handle = self.cf(cf)?;
 
         self.db
-            .get_cf(cf, key)
-            .map_err(|e| DatabaseError::RocksDBError(e))
+            .put_cf(cf_handle, key, value)
+            .map_err(|source| rocksdb_error(cf, key, source))
     }
 
-    fn delete(&self, cf: &str, key: &[u8]) -> Result<(), DatabaseError> {
-        let cf = self
-            .db
-            .cf_handle(cf)
-            .ok_or(DatabaseError::ColumnNotFound(cf.to_string()))?;
+    fn get(&self, cf: &str, key: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError> {
+        let cf_handle = self.cf(cf)?;
 
         self.db
-            .delete_cf(cf, key)
-            .map_err(|e| DatabaseError::RocksDBError(e))
+            .get_cf(cf_handle, key)
+            .map_err(|source| rocksdb_error(cf, key, source))
     }
 
-    fn iter(&self, cf: &str) -> Result<rocksdb::DBIterator, DatabaseError> {
-        let cf = self
-            .db
-            .cf_handle(cf)
-            .ok_or(DatabaseError::ColumnNotFound(cf.to_string()))?;
+    /// Decoded value of `key` in `cf`.
+    fn get_decoded<T: DeserializeOwned>(
+        &self,
+        cf: &str,
+        key: &[u8],
+    ) -> Result<Option<T>, DatabaseError> {
+        self.get(cf, key)?
+            .map(|encoded| decode(cf, key, &encoded))
+            .transpose()
+    }
+
+    /// Iterate over all the entries of `cf`, in key order.
+    fn iter(
+        &self,
+        cf: &str,
+    ) -> Result<impl Iterator<Item = Result<Entry, DatabaseError>> + '_, DatabaseError> {
+        self.iter_prefix_from(cf, &[], &[])
+    }
+
+    /// Iterate over the entries whose key starts with `prefix`, in key order.
+    fn iter_prefix(
+        &self,
+        cf: &str,
+        prefix: &[u8],
+    ) -> Result<impl Iterator<Item = Result<Entry, DatabaseError>> + '_, DatabaseError> {
+        self.iter_prefix_from(cf, prefix, &[])
+    }
 
-        let mode = rocksdb::IteratorMode::Start;
+    /// Iterate over the entries whose key starts with `prefix`, in key order,
+    /// starting at `prefix || start`.
+    fn iter_prefix_from(
+        &self,
+        cf: &str,
+        prefix: &[u8],
+        start: &[u8],
+    ) -> Result<impl Iterator<Item = Result<Entry, DatabaseError>> + '_, DatabaseError> {
+        let name = cf.to_string();
+        let cf = self.cf(cf)?;
+        let from = [prefix, start].concat();
+        let mode = rocksdb::IteratorMode::From(&from, rocksdb::Direction::Forward);
+        let prefix = prefix.to_vec();
+        Ok(self
+            .db
+            .iterator_cf(cf, mode)
+            .map(move |item| item.map_err(|source| iterator_error(&name, source)))
+            .take_while(move |item| match item {
+                Ok((key, _)) => key.starts_with(&prefix),
+                Err(_) => true,
+            }))
+    }
 
-        Ok(self.db.iterator_cf(cf, mode))
+    /// Add to `batch` the deletion of all the entries whose key is greater
+    /// or equal to `from`.
+    fn delete_from(
+        &self,
+        batch: &mut WriteBatch,
+        cf: &str,
+        from: &[u8],
+    ) -> Result<(), DatabaseError> {
+        let cf_handle = self.cf(cf)?;
+        let mode = rocksdb::IteratorMode::From(from, rocksdb::Direction::Forward);
+        for item in self.db.iterator_cf(cf_handle, mode) {
+            let (key, _) = item.map_err(|source| iterator_error(cf, source))?;
+            batch.delete_cf(cf_handle, key);
+        }
+        Ok(())
     }
 
     pub fn destroy(&self) -> Result<(), DatabaseError> {
         DB::destroy(&Options::default(), self.path.as_str())
-            .map_err(|e| DatabaseError::RocksDBError(e))
+            .map_err(|source| path_error(&self.path, source))
     }
 
     pub fn repair(&self) -> Result<(), DatabaseError> {
         DB::repair(&Options::default(), self.path.as_str())
-            .map_err(|e| DatabaseError::RocksDBError(e))
+            .map_err(|source| path_error(&self.path, source))
     }
 
     pub fn flush(&self) -> Result<(), DatabaseError> {
-        self.db.flush().map_err(|e| DatabaseError::RocksDBError(e))
+        self.db
+            .flush()
+            .map_err(|source| path_error(&self.path, source))
     }
 
-    pub fn insert_key(
+    pub fn get_key(
         &self,
         contract: StarkFelt,
         key: StarkFelt,
-        value: StarkFelt,
-        index: u64,
-    ) -> Result<(), DatabaseError> {
-        let mut db_key = Vec::new();
-        db_key.extend_from_slice(contract.bytes());
-        db_key.extend_from_slice(key.bytes());
-
-        let encoded = self.get("key", &db_key)?;
-        let mut history: History<StarkFelt> = match encoded {
-            Some(encoded) => {
-                bincode::deserialize(&encoded).map_err(|_| DatabaseError::DecodeError)?
-            }
-            None => History::new(),
-        };
-
-        history
-            .push(index, value)
-            .map_err(|_| DatabaseError::HistoryError)?;
-        let encoded = bincode::serialize(&history).map_err(|_| DatabaseError::EncodeError)?;
-
-        self.insert("key", &db_key, &encoded)
+    ) -> Result<Option<StarkFelt>, DatabaseError> {
+        self.get_key_at(contract, key, u64::MAX)
     }
 
-    pub fn get_key(
+    pub fn get_key_at(
         &self,
         contract: StarkFelt,
         key: StarkFelt,
+        index: u64,
     ) -> Result<Option<StarkFelt>, DatabaseError> {
-        let mut db_key = Vec::new();
-        db_key.extend_from_slice(contract.bytes());
-        db_key.extend_from_slice(key.bytes());
+        let db_key = storage_key(&contract, &key);
 
-        let encoded = self.get("key", &db_key)?;
-        let history: History<StarkFelt> = match encoded {
-            Some(encoded) => {
-                bincode::deserialize(&encoded).map_err(|_| DatabaseError::DecodeError)?
+        match self.metadata.storage_layout {
+            StorageLayout::History => {
+                let history: Option<History<StarkFelt>> = self.get_decoded("key", &db_key)?;
+                Ok(history.and_then(|history| history.get_at(index).cloned()))
             }
-            None => return Ok(None),
-        };
+            StorageLayout::Flat => self.get_flat_value_at(&db_key, index),
+        }
+    }
 
-        Ok(history.get().cloned())
+    /// Value of a slot of the flat layout at a block: the last change at or
+    /// before it, found by a reverse seek from `contract || key || block`.
+    fn get_flat_value_at(
+        &self,
+        db_key: &[u8],
+        index: u64,
+    ) -> Result<Option<StarkFelt>, DatabaseError> {
+        let cf = self.cf("storage")?;
+        let from = [db_key, &index.to_be_bytes()].concat();
+        let mode = rocksdb::IteratorMode::From(&from, rocksdb::Direction::Reverse);
+        match self.db.iterator_cf(cf, mode).next() {
+            Some(item) => {
+                let (change_key, encoded) =
+                    item.map_err(|source| iterator_error("storage", source))?;
+                if change_key.starts_with(db_key) {
+                    Ok(Some(decode_felt("storage", &change_key, &encoded)?))
+                } else {
+                    Ok(None)
+                }
+            }
+            None => Ok(None),
+        }
     }
 
-    pub fn get_key_at(
+    /// Add to `batch` a change of a storage slot, returning its previous value.
+    fn put_storage(
         &self,
+        batch: &mut WriteBatch,
         contract: StarkFelt,
         key: StarkFelt,
         index: u64,
+        value: StarkFelt,
     ) -> Result<Option<StarkFelt>, DatabaseError> {
-        let mut db_key = Vec::new();
-        db_key.extend_from_slice(contract.bytes());
-        db_key.extend_from_slice(key.bytes());
+        let db_key = storage_key(&contract, &key);
 
-        let encoded = self.get("key", &db_key)?;
-        let history: History<StarkFelt> = match encoded {
-            Some(encoded) => {
-                bincode::deserialize(&encoded).map_err(|_| DatabaseError::DecodeError)?
+        match self.metadata.storage_layout {
+            StorageLayout::History => {
+                let mut history: History<StarkFelt> = match self.get("key", &db_key)? {
+                    Some(encoded) => decode("key", &db_key, &encoded)?,
+                    None => History::new(),
+                };
+                let previous = history.get().copied();
+                history
+                    .push(index, value)
+                    .map_err(|source| history_error("key", &db_key, source))?;
+                let encoded = encode("key", &db_key, &history)?;
+                batch.put_cf(self.cf("key")?, db_key, encoded);
+                Ok(previous)
             }
-            None => return Ok(None),
-        };
-
-        Ok(history.get_at(index).cloned())
+            StorageLayout::Flat => {
+                let cf = self.cf("storage")?;
+                let mode = rocksdb::IteratorMode::From(
+                    &[&db_key[..], &u64::MAX.to_be_bytes()].concat(),
+                    rocksdb::Direction::Reverse,
+                );
+                let last = match self.db.iterator_cf(cf, mode).next() {
+                    Some(item) => {
+                        let (change_key, encoded) =
+                            item.map_err(|source| iterator_error("storage", source))?;
+                        match change_key.strip_prefix(&db_key[..]) {
+                            Some(block) => Some((
+                                decode_block("storage", &change_key, block)?,
+                                decode_felt("storage", &change_key, &encoded)?,
+                            )),
+                            None => None,
+                        }
+                    }
+                    None => None,
+                };
+                // same ordering rule as `History::push`
+                if let Some((last, _)) = last.filter(|(last, _)| index <= *last) {
+                    return Err(history_error(
+                        "storage",
+                        &db_key,
+                        HistoryError::NonMonotonicIndex {
+                            last,
+                            attempted: index,
+                        },
+                    ));
+                }
+                batch.put_cf(
+                    cf,
+                    [&db_key[..], &index.to_be_bytes()].concat(),
+                    value.bytes(),
+                );
+                Ok(last.map(|(_, previous)| previous))
+            }
+        }
     }
 
-    pub fn insert_nonce(
+    /// Storage of a contract as of a block: every slot with a non-zero value,
+    /// as `(key, value)` sorted by key, starting at `from_key`.
+    ///
+    /// Slots are read lazily so large contracts can be streamed; resume a
+    /// partial read from the key following the last one returned.
+    pub fn storage_at(
         &self,
         contract: StarkFelt,
-        nonce: StarkFelt,
-        index: u64,
-    ) -> Result<(), DatabaseError> {
-        let db_key = contract.bytes();
+        block_number: u64,
+        from_key: StarkFelt,
+    ) -> Result<Slots<'_>, DatabaseError> {
+        if self.metadata.storage_layout == StorageLayout::Flat {
+            return Ok(Box::new(self.flat_storage_at(
+                contract,
+                block_number,
+                from_key,
+            )?));
+        }
+        Ok(Box::new(
+            self.iter_prefix_from("key", contract.bytes(), from_key.bytes())?
+                .filter_map(move |item| {
+                    let decode = || {
+                        let (db_key, encoded) = item?;
+                        let key = db_key
+                            .get(32..)
+                            .and_then(|key| key.try_into().ok())
+                            .and_then(|key| StarkFelt::new(key).ok())
+                            .ok_or_else(|| invalid_entry("key", &db_key))?;
+                        let history: History<StarkFelt> = decode("key", &db_key, &encoded)?;
+                        Ok(history
+                            .get_at(block_number)
+                            .filter(|value| **value != StarkFelt::default())
+                            .map(|value| (key, *value)))
+                    };
+                    decode().transpose()
+                }),
+        ))
+    }
 
-        let encoded = self.get("contract", db_key)?;
-        let mut contract: Contract = match encoded {
-            Some(encoded) => {
-                bincode::deserialize(&encoded).map_err(|_| DatabaseError::DecodeError)?
+    /// `storage_at` for the flat layout: one seek to the first change of each
+    /// slot, then a reverse seek to its value at the block.
+    fn flat_storage_at(
+        &self,
+        contract: StarkFelt,
+        block_number: u64,
+        from_key: StarkFelt,
+    ) -> Result<
+        impl Iterator<Item = Result<(StarkFelt, StarkFelt), DatabaseError>> + '_,
+        DatabaseError,
+    > {
+        let cf = self.cf("storage")?;
+        let mut next = storage_key(&contract, &from_key);
+        let mut done = false;
+        Ok(std::iter::from_fn(move || {
+            let mut next_slot = || {
+                loop {
+                    let mode = rocksdb::IteratorMode::From(&next, rocksdb::Direction::Forward);
+                    let Some(item) = self.db.iterator_cf(cf, mode).next() else {
+                        return Ok(None);
+                    };
+                    let (change_key, _) =
+                        item.map_err(|source| iterator_error("storage", source))?;
+                    if !change_key.starts_with(contract.bytes()) {
+                        return Ok(None);
+                    }
+                    let db_key = change_key
+                        .get(..64)
+                        .ok_or_else(|| invalid_entry("storage", &change_key))?;
+                    let key = decode_felt("storage", &change_key, &db_key[32..])?;
+                    // right after the last possible change of the slot
+                    next = [db_key, &u64::MAX.to_be_bytes(), &[0]].concat();
+                    if let Some(value) = self.get_flat_value_at(db_key, block_number)? {
+                        if value != StarkFelt::default() {
+                            return Ok(Some((key, value)));
+                        }
+                    }
+                }
+            };
+            if done {
+                return None;
             }
-            None => Contract::new(),
-        };
-
-        contract
-            .push_nonce(index, nonce)
-            .map_err(|_| DatabaseError::HistoryError)?;
+            let slot = next_slot().transpose();
+            // stop after the last slot or an error
+            done = !matches!(slot, Some(Ok(_)));
+            slot
+        }))
+    }
 
-        let encoded = bincode::serialize(&contract).map_err(|_| DatabaseError::EncodeError)?;
+    /// Every change of a storage slot, empty if it was never set.
+    pub fn get_key_history(
+        &self,
+        contract: StarkFelt,
+        key: StarkFelt,
+    ) -> Result<History<StarkFelt>, DatabaseError> {
+        let db_key = storage_key(&contract, &key);
 
-        self.insert("contract", db_key, &encoded)
+        if self.metadata.storage_layout == StorageLayout::Flat {
+            let mut history = History::new();
+            for item in self.iter_prefix("storage", &db_key)? {
+                let (change_key, encoded) = item?;
+                history
+                    .push(
+                        decode_block("storage", &change_key, &change_key[64..])?,
+                        decode_felt("storage", &change_key, &encoded)?,
+                    )
+                    .map_err(|source| history_error("storage", &db_key, source))?;
+            }
+            return Ok(history);
+        }
+        Ok(self
+            .get_decoded("key", &db_key)?
+            .unwrap_or_else(History::new))
     }
 
     pub fn get_nonce(&self, contract: StarkFelt) -> Result<Option<StarkFelt>, DatabaseError> {
         let db_key = contract.bytes();
 
-        let encoded = self.get("contract", db_key)?;
-        let contract: Contract = match encoded {
-            Some(encoded) => {
-                bincode::deserialize(&encoded).map_err(|_| DatabaseError::DecodeError)?
-            }
-            None => return Ok(None),
+        let Some(contract) = self.get_decoded::<Contract>("contract", db_key)? else {
+            return Ok(None);
         };
 
         Ok(contract.get_nonce().cloned())
@@ -217,51 +891,29 @@ impl Database {
     ) -> Result<Option<StarkFelt>, DatabaseError> {
         let db_key = contract.bytes();
 
-        let encoded = self.get("contract", db_key)?;
-        let contract: Contract = match encoded {
-            Some(encoded) => {
-                bincode::deserialize(&encoded).map_err(|_| DatabaseError::DecodeError)?
-            }
-            None => return Ok(None),
+        let Some(contract) = self.get_decoded::<Contract>("contract", db_key)? else {
+            return Ok(None);
         };
 
         Ok(contract.get_nonce_at(index).cloned())
     }
 
-    pub fn insert_class_hash(
+    /// Every change of the nonce of a contract, empty for unknown contracts.
+    pub fn get_nonce_history(
         &self,
         contract: StarkFelt,
-        class_hash: StarkFelt,
-        index: u64,
-    ) -> Result<(), DatabaseError> {
-        let db_key = contract.bytes();
-
-        let encoded = self.get("contract", db_key)?;
-        let mut contract: Contract = match encoded {
-            Some(encoded) => {
-                bincode::deserialize(&encoded).map_err(|_| DatabaseError::DecodeError)?
-            }
-            None => Contract::new(),
-        };
-
-        contract
-            .push_class_hash(index, class_hash)
-            .map_err(|_| DatabaseError::HistoryError)?;
-
-        let encoded = bincode::serialize(&contract).map_err(|_| DatabaseError::EncodeError)?;
-
-        self.insert("contract", db_key, &encoded)
+    ) -> Result<History<StarkFelt>, DatabaseError> {
+        Ok(self
+            .get_contract(contract)?
+            .map(|contract| contract.nonce_history().clone())
+            .unwrap_or_else(History::new))
     }
 
     pub fn get_class_hash(&self, contract: StarkFelt) -> Result<Option<StarkFelt>, DatabaseError> {
         let db_key = contract.bytes();
 
-        let encoded = self.get("contract", db_key)?;
-        let contract: Contract = match encoded {
-            Some(encoded) => {
-                bincode::deserialize(&encoded).map_err(|_| DatabaseError::DecodeError)?
-            }
-            None => return Ok(None),
+        let Some(contract) = self.get_decoded::<Contract>("contract", db_key)? else {
+            return Ok(None);
         };
 
         Ok(contract.get_class_hash().cloned())
@@ -274,60 +926,1828 @@ impl Database {
     ) -> Result<Option<StarkFelt>, DatabaseError> {
         let db_key = contract.bytes();
 
-        let encoded = self.get("contract", db_key)?;
-        let contract: Contract = match encoded {
-            Some(encoded) => {
-                bincode::deserialize(&encoded).map_err(|_| DatabaseError::DecodeError)?
-            }
-            None => return Ok(None),
+        let Some(contract) = self.get_decoded::<Contract>("contract", db_key)? else {
+            return Ok(None);
         };
 
         Ok(contract.get_class_hash_at(index).cloned())
     }
 
-    pub fn revert_to(&self, index: u64) -> Result<(), DatabaseError> {
-        let cf_handle = self
-            .db
-            .cf_handle("key")
-            .ok_or(DatabaseError::ColumnNotFound("key".to_string()))?;
-        let iter = self.db.iterator_cf(cf_handle, rocksdb::IteratorMode::Start);
-        for item in iter {
-            let (key, encoded) = item.map_err(|_| DatabaseError::IteratorError)?;
+    /// Every class hash of a contract: its deployment then its class
+    /// replacements. Empty for unknown contracts.
+    pub fn get_class_hash_history(
+        &self,
+        contract: StarkFelt,
+    ) -> Result<History<StarkFelt>, DatabaseError> {
+        Ok(self
+            .get_contract(contract)?
+            .map(|contract| contract.class_hash_history().clone())
+            .unwrap_or_else(History::new))
+    }
 
-            let mut history: History<StarkFelt> =
-                bincode::deserialize(&*encoded).map_err(|_| DatabaseError::DecodeError)?;
+    /// Contracts matching `filter`, sorted by address, starting at
+    /// `from_address` and returning at most `limit` of them.
+    pub fn list_contracts(
+        &self,
+        filter: &ContractFilter,
+        from_address: StarkFelt,
+        limit: usize,
+    ) -> Result<ContractsPage, DatabaseError> {
+        let mut page = ContractsPage {
+            contracts: Vec::new(),
+            next_address: None,
+        };
+        for item in self.iter_prefix_from("contract", &[], from_address.bytes())? {
+            let (key, encoded) = item?;
+            let address = key
+                .as_ref()
+                .try_into()
+                .ok()
+                .and_then(|key| StarkFelt::new(key).ok())
+                .ok_or_else(|| invalid_entry("contract", &key))?;
+            let contract: Contract = decode("contract", &key, &encoded)?;
+            let summary = contract.summary(address);
 
-            history.revert_to(index);
-            if history.is_empty() {
-                self.delete("key", &*key)?;
-            } else {
-                let encoded =
-                    bincode::serialize(&history).map_err(|_| DatabaseError::EncodeError)?;
-                self.insert("key", &*key, &encoded)?;
+            if !filter.matches(&summary) {
+                continue;
+            }
+
+            if page.contracts.len() == limit {
+                page.next_address = Some(address);
+                break;
             }
+            page.contracts.push(summary);
         }
+        Ok(page)
+    }
 
-        let cf_handle = self
-            .db
-            .cf_handle("contract")
-            .ok_or(DatabaseError::ColumnNotFound("contract".to_string()))?;
-        let iter = self.db.iterator_cf(cf_handle, rocksdb::IteratorMode::Start);
-        for item in iter {
-            let (key, encoded) = item.map_err(|_| DatabaseError::IteratorError)?;
+    fn get_contract(&self, contract: StarkFelt) -> Result<Option<Contract>, DatabaseError> {
+        self.get_decoded("contract", contract.bytes())
+    }
 
-            let mut contract: Contract =
-                bincode::deserialize(&*encoded).map_err(|_| DatabaseError::DecodeError)?;
+    /// Every contract that used a class, with the block range it used it
+    /// during, sorted by contract.
+    pub fn get_class_usage(&self, class_hash: StarkFelt) -> Result<Vec<ClassUsage>, DatabaseError> {
+        self.iter_prefix("class_usage", class_hash.bytes())?
+            .map(|item| {
+                let (key, encoded) = item?;
+                let (_, contract, from_block) = decode_class_usage_key(&key)
+                    .ok_or_else(|| invalid_entry("class_usage", &key))?;
+                let to_block: Option<u64> = decode("class_usage", &key, &encoded)?;
+                Ok(ClassUsage {
+                    contract,
+                    from_block,
+                    to_block,
+                })
+            })
+            .collect()
+    }
 
-            contract.revert_to(index);
-            if contract.is_empty() {
-                self.delete("contract", &*key)?;
-            } else {
-                let encoded =
-                    bincode::serialize(&contract).map_err(|_| DatabaseError::EncodeError)?;
-                self.insert("contract", &*key, &encoded)?;
+    fn get_class(&self, class_hash: StarkFelt) -> Result<Option<Class>, DatabaseError> {
+        self.get_decoded("class", class_hash.bytes())
+    }
+
+    /// When the class was declared, and whether it is a Cairo 0 or Cairo 1 class.
+    pub fn get_class_declaration(
+        &self,
+        class_hash: StarkFelt,
+    ) -> Result<Option<ClassDeclaration>, DatabaseError> {
+        Ok(self.get_class(class_hash)?.map(|class| class.declaration()))
+    }
+
+    /// Compiled class hash (CASM) of a Cairo 1 class at a given block.
+    pub fn get_compiled_class_hash_at(
+        &self,
+        class_hash: StarkFelt,
+        index: u64,
+    ) -> Result<Option<StarkFelt>, DatabaseError> {
+        Ok(self
+            .get_class(class_hash)?
+            .and_then(|class| class.get_compiled_class_hash_at(index).cloned()))
+    }
+
+    pub fn get_sync_cursor(&self) -> Result<Option<SyncCursor>, DatabaseError> {
+        self.get_decoded("meta", SYNC_CURSOR_KEY)
+    }
+
+    /// Apply the state diff of a block, its header and the sync cursor in a
+    /// single atomic write, so the database is always consistent at a block
+    /// boundary.
+    ///
+    /// Once a block was applied, only the block right after the sync cursor
+    /// is accepted, so the histories have no gap.
+    pub fn apply_block(
+        &self,
+        block_number: u64,
+        block: &Block,
+        state_update: &StateUpdate,
+    ) -> Result<(), DatabaseError> {
+        let _guard = self.lock_writes();
+        // checked under the lock: a revert can move the cursor back meanwhile
+        if let Some(cursor) = self.get_sync_cursor()? {
+            if cursor.block_number.checked_add(1) != Some(block_number) {
+                return Err(DatabaseError::NonContiguousBlock {
+                    block_number,
+                    last: cursor.block_number,
+                });
             }
         }
+        let state_diff = &state_update.state_diff;
+        let mut batch = WriteBatch::default();
+        let mut changes = ChangeSet::default();
 
-        Ok(())
+        // merge all the updates of a contract before writing it
+        let mut contracts = HashMap::new();
+        let cf = self.cf("class_usage")?;
+        for deployed_contract in state_diff
+            .deployed_contracts
+            .iter()
+            .chain(&state_diff.replaced_classes)
+        {
+            let address = deployed_contract.address;
+            let contract = self.contract_entry(&mut contracts, address)?;
+            let previous = contract.class_hash_history().last();
+            // the class replaced by this block stops being used
+            if let Some((from_block, previous)) = previous {
+                let db_key = class_usage_key(previous, &address, from_block);
+                let encoded = encode("class_usage", &db_key, &Some(block_number - 1))?;
+                batch.put_cf(cf, db_key, encoded);
+            }
+            changes.class_hashes.push(ContractChange {
+                contract: address,
+                previous: previous.map(|(_, class_hash)| *class_hash),
+                value: deployed_contract.class_hash,
+            });
+            contract
+                .push_class_hash(block_number, deployed_contract.class_hash)
+                .map_err(|source| history_error("contract", address.bytes(), source))?;
+            let db_key = class_usage_key(&deployed_contract.class_hash, &address, block_number);
+            let encoded = encode("class_usage", &db_key, &None::<u64>)?;
+            batch.put_cf(cf, db_key, encoded);
+        }
+        for (contract, nonce) in &state_diff.nonces {
+            let entry = self.contract_entry(&mut contracts, *contract)?;
+            changes.nonces.push(ContractChange {
+                contract: *contract,
+                previous: entry.get_nonce().copied(),
+                value: *nonce,
+            });
+            entry
+                .push_nonce(block_number, *nonce)
+                .map_err(|source| history_error("contract", contract.bytes(), source))?;
+        }
+        let cf = self.cf("contract")?;
+        for (address, contract) in &contracts {
+            let encoded = encode("contract", address.bytes(), contract)?;
+            batch.put_cf(cf, address.bytes(), encoded);
+        }
+
+        let mut classes = HashMap::new();
+        for class_hash in &state_diff.old_declared_contracts {
+            if self.get_class(*class_hash)?.is_none() {
+                classes.insert(*class_hash, Class::new_cairo0(block_number));
+                changes.declared_classes.push(*class_hash);
+            }
+        }
+        for DeclaredClass {
+            class_hash,
+            compiled_class_hash,
+        } in state_diff
+            .declared_classes
+            .iter()
+            .chain(&state_diff.migrated_compiled_classes)
+        {
+            if let hash_map::Entry::Vacant(entry) = classes.entry(*class_hash) {
+                let class = match self.get_class(*class_hash)? {
+                    // the compiled class hash of an existing class changed
+                    Some(mut class) => {
+                        changes.compiled_class_hashes.push(CompiledClassHashChange {
+                            class_hash: *class_hash,
+                            previous: class.get_compiled_class_hash_at(block_number).copied(),
+                            value: *compiled_class_hash,
+                        });
+                        class
+                            .push_compiled_class_hash(block_number, *compiled_class_hash)
+                            .map_err(|source| DatabaseError::ClassError {
+                                class_hash: *class_hash,
+                                source,
+                            })?;
+                        class
+                    }
+                    None => {
+                        changes.declared_classes.push(*class_hash);
+                        Class::new_cairo1(block_number, *compiled_class_hash)
+                    }
+                };
+                entry.insert(class);
+            }
+        }
+        let cf = self.cf("class")?;
+        for (class_hash, class) in &classes {
+            let encoded = encode("class", class_hash.bytes(), class)?;
+            batch.put_cf(cf, class_hash.bytes(), encoded);
+        }
+
+        for (contract, storage_diffs) in &state_diff.storage_diffs {
+            for StorageDiff { key, value } in storage_diffs {
+                let previous =
+                    self.put_storage(&mut batch, *contract, *key, block_number, *value)?;
+                changes.storage.push(StorageChange {
+                    contract: *contract,
+                    key: *key,
+                    previous,
+                    value: *value,
+                });
+            }
+        }
+
+        let cf = self.cf("transaction")?;
+        let cf_hash = self.cf("transaction_hash")?;
+        let cf_account = self.cf("account_transaction")?;
+        for (index, transaction) in block.transactions.iter().enumerate() {
+            let db_key = transaction_key(block_number, index as u64);
+            let encoded = encode("transaction", &db_key, transaction)?;
+            batch.put_cf(cf, db_key, encoded);
+            batch.put_cf(cf_hash, transaction.transaction_hash.bytes(), db_key);
+            if let Some((account, nonce)) = transaction.account_nonce() {
+                batch.put_cf(
+                    cf_account,
+                    account_transaction_key(&account, &nonce),
+                    db_key,
+                );
+            }
+        }
+        let cf = self.cf("receipt")?;
+        for receipt in &block.transaction_receipts {
+            let db_key = transaction_key(block_number, receipt.transaction_index);
+            let encoded = encode("receipt", &db_key, receipt)?;
+            batch.put_cf(cf, db_key, encoded);
+        }
+
+        let cf = self.cf("event")?;
+        let cf_address = self.cf("event_address")?;
+        let cf_key = self.cf("event_key")?;
+        for receipt in &block.transaction_receipts {
+            for (index, event) in receipt.events.iter().enumerate() {
+                let position =
+                    EventPosition::new(block_number, receipt.transaction_index, index as u64);
+                let stored = StoredEvent {
+                    transaction_hash: receipt.transaction_hash,
+                    from_address: event.from_address,
+                    keys: event.keys.clone(),
+                    data: event.data.clone(),
+                };
+                let encoded = encode("event", &position.to_key(), &stored)?;
+                batch.put_cf(cf, position.to_key(), encoded);
+                let (address_key, first_key_key) = event_index_keys(&stored, position);
+                batch.put_cf(cf_address, address_key, []);
+                if let Some(first_key_key) = first_key_key {
+                    batch.put_cf(cf_key, first_key_key, []);
+                }
+            }
+        }
+
+        let encoded = encode("state_diff", &block_number.to_be_bytes(), state_diff)?;
+        batch.put_cf(self.cf("state_diff")?, block_number.to_be_bytes(), encoded);
+        let encoded = encode("change_set", &block_number.to_be_bytes(), &changes)?;
+        batch.put_cf(self.cf("change_set")?, block_number.to_be_bytes(), encoded);
+
+        let header = BlockHeader::new(block_number, block, state_update);
+        let encoded = encode("block", &block_number.to_be_bytes(), &header)?;
+        batch.put_cf(self.cf("block")?, block_number.to_be_bytes(), encoded);
+        batch.put_cf(
+            self.cf("block_hash")?,
+            state_update.block_hash.bytes(),
+            block_number.to_be_bytes(),
+        );
+
+        let cursor = SyncCursor {
+            block_number,
+            block_hash: Some(state_update.block_hash),
+        };
+        let encoded = encode("meta", SYNC_CURSOR_KEY, &cursor)?;
+        batch.put_cf(self.cf("meta")?, SYNC_CURSOR_KEY, encoded);
+
+        self.write(batch, &format!("block {block_number}"))
+    }
+
+    /// Get the contract from `contracts`, loading it from the database on first access.
+    fn contract_entry<'a>(
+        &self,
+        contracts: &'a mut HashMap<StarkFelt, Contract>,
+        address: StarkFelt,
+    ) -> Result<&'a mut Contract, DatabaseError> {
+        match contracts.entry(address) {
+            hash_map::Entry::Occupied(entry) => Ok(entry.into_mut()),
+            hash_map::Entry::Vacant(entry) => {
+                let contract = self
+                    .get_decoded("contract", address.bytes())?
+                    .unwrap_or_else(Contract::new);
+                Ok(entry.insert(contract))
+            }
+        }
+    }
+
+    pub fn get_block_header(
+        &self,
+        block_number: u64,
+    ) -> Result<Option<BlockHeader>, DatabaseError> {
+        self.get_decoded("block", &block_number.to_be_bytes())
+    }
+
+    /// Block number of the block with the given hash.
+    pub fn get_block_number(&self, block_hash: StarkFelt) -> Result<Option<u64>, DatabaseError> {
+        match self.get("block_hash", block_hash.bytes())? {
+            Some(encoded) => {
+                let bytes = encoded
+                    .as_slice()
+                    .try_into()
+                    .map_err(|_| invalid_entry("block_hash", block_hash.bytes()))?;
+                Ok(Some(u64::from_be_bytes(bytes)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_block_header_by_hash(
+        &self,
+        block_hash: StarkFelt,
+    ) -> Result<Option<BlockHeader>, DatabaseError> {
+        match self.get_block_number(block_hash)? {
+            Some(block_number) => self.get_block_header(block_number),
+            None => Ok(None),
+        }
+    }
+
+    /// State diff applied by a block, as received from the feeder gateway.
+    pub fn get_state_diff(&self, block_number: u64) -> Result<Option<StateDiff>, DatabaseError> {
+        self.get_decoded("state_diff", &block_number.to_be_bytes())
+    }
+
+    /// State changed by a block, with the values it overwrote. `None` for
+    /// blocks applied before change sets were recorded.
+    pub fn get_change_set(&self, block_number: u64) -> Result<Option<ChangeSet>, DatabaseError> {
+        self.get_decoded("change_set", &block_number.to_be_bytes())
+    }
+
+    /// Check that each block in `from..=to` starts from the state root the
+    /// previous block ended with. Returns the blocks breaking the chain,
+    /// missing headers included.
+    pub fn check_chain_continuity(&self, from: u64, to: u64) -> Result<Vec<u64>, DatabaseError> {
+        let mut broken = Vec::new();
+        let mut previous = match from.checked_sub(1) {
+            Some(parent) => self.get_block_header(parent)?,
+            None => None,
+        };
+        for block_number in from..=to {
+            let header = self.get_block_header(block_number)?;
+            match (&previous, &header) {
+                (Some(parent), Some(header)) if parent.new_root != header.old_root => {
+                    broken.push(block_number)
+                }
+                (_, None) => broken.push(block_number),
+                _ => {}
+            }
+            previous = header;
+        }
+        Ok(broken)
+    }
+
+    pub fn get_transaction(
+        &self,
+        block_number: u64,
+        index: u64,
+    ) -> Result<Option<Transaction>, DatabaseError> {
+        self.get_decoded("transaction", &transaction_key(block_number, index))
+    }
+
+    pub fn get_receipt(
+        &self,
+        block_number: u64,
+        index: u64,
+    ) -> Result<Option<Receipt>, DatabaseError> {
+        self.get_decoded("receipt", &transaction_key(block_number, index))
+    }
+
+    /// Transactions of a block, in execution order.
+    pub fn get_block_transactions(
+        &self,
+        block_number: u64,
+    ) -> Result<Vec<Transaction>, DatabaseError> {
+        let prefix = block_number.to_be_bytes();
+        self.iter_prefix("transaction", &prefix)?
+            .map(|item| {
+                let (key, encoded) = item?;
+                decode("transaction", &key, &encoded)
+            })
+            .collect()
+    }
+
+    /// Receipts of the transactions of a block, in execution order.
+    pub fn get_block_receipts(&self, block_number: u64) -> Result<Vec<Receipt>, DatabaseError> {
+        let prefix = block_number.to_be_bytes();
+        self.iter_prefix("receipt", &prefix)?
+            .map(|item| {
+                let (key, encoded) = item?;
+                decode("receipt", &key, &encoded)
+            })
+            .collect()
+    }
+
+    /// Block and index of the transaction with the given hash.
+    pub fn get_transaction_location(
+        &self,
+        transaction_hash: StarkFelt,
+    ) -> Result<Option<TransactionLocation>, DatabaseError> {
+        match self.get("transaction_hash", transaction_hash.bytes())? {
+            Some(encoded) => Ok(Some(transaction_location(&encoded).ok_or_else(|| {
+                invalid_entry("transaction_hash", transaction_hash.bytes())
+            })?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Transactions sent by an account, ordered by nonce, starting at
+    /// `from_nonce` and returning at most `limit` of them.
+    pub fn get_account_transactions(
+        &self,
+        account: StarkFelt,
+        from_nonce: StarkFelt,
+        limit: usize,
+    ) -> Result<Vec<(StarkFelt, TransactionLocation)>, DatabaseError> {
+        self.iter_prefix_from("account_transaction", account.bytes(), from_nonce.bytes())?
+            .take(limit)
+            .map(|item| {
+                let (key, encoded) = item?;
+                let nonce = decode_felt("account_transaction", &key, &key[32..])?;
+                let location = transaction_location(&encoded)
+                    .ok_or_else(|| invalid_entry("account_transaction", &key))?;
+                Ok((nonce, location))
+            })
+            .collect()
+    }
+
+    /// Cross-check the transactions indexed for an account against its nonce
+    /// history: a transaction with nonce `n` must be in the block where the
+    /// nonce of the account moved from at most `n` to more than `n`.
+    ///
+    /// Only meaningful for the blocks synced since the account was deployed.
+    pub fn check_account_nonces(
+        &self,
+        account: StarkFelt,
+    ) -> Result<Vec<NonceMismatch>, DatabaseError> {
+        let contract: Contract = self
+            .get_decoded("contract", account.bytes())?
+            .unwrap_or_else(Contract::new);
+
+        let mut mismatches = Vec::new();
+        for (nonce, location) in
+            self.get_account_transactions(account, StarkFelt::default(), usize::MAX)?
+        {
+            let nonce_before = location
+                .block_number
+                .checked_sub(1)
+                .and_then(|parent| contract.get_nonce_at(parent))
+                .copied();
+            let nonce_after = contract.get_nonce_at(location.block_number).copied();
+            let before_ok = nonce_before.unwrap_or_default() <= nonce;
+            let after_ok = nonce_after.is_some_and(|after| after > nonce);
+            if !before_ok || !after_ok {
+                mismatches.push(NonceMismatch {
+                    nonce,
+                    location,
+                    nonce_before,
+                    nonce_after,
+                });
+            }
+        }
+        Ok(mismatches)
+    }
+
+    /// Events matching `filter`, in chain order, starting at the `continuation`
+    /// token of the previous page.
+    ///
+    /// Events are looked up in the `(address, first key)` index when the filter
+    /// gives both, in the `address` index when it only gives the address, and
+    /// scanned over the block range otherwise.
+    pub fn get_events(
+        &self,
+        filter: &EventFilter,
+        continuation: Option<EventPosition>,
+        chunk_size: usize,
+    ) -> Result<EventsPage, DatabaseError> {
+        let chunk_size = chunk_size.max(1);
+        let start =
+            continuation
+                .unwrap_or_default()
+                .max(EventPosition::new(filter.from_block, 0, 0));
+        let to_block = filter.to_block.unwrap_or(u64::MAX);
+        let start_key = start.to_key();
+
+        let (cf, prefixes) = match (filter.address, filter.keys.first()) {
+            (Some(address), Some(first_keys)) if !first_keys.is_empty() => (
+                "event_key",
+                first_keys
+                    .iter()
+                    .map(|key| [address.bytes(), key.bytes()].concat())
+                    .collect(),
+            ),
+            (Some(address), _) => ("event_address", vec![address.bytes().to_vec()]),
+            (None, _) => ("event", vec![Vec::new()]),
+        };
+        let mut candidates: Vec<Box<dyn Iterator<Item = Result<Entry, DatabaseError>> + '_>> =
+            Vec::new();
+        for prefix in &prefixes {
+            candidates.push(Box::new(self.iter_prefix_from(cf, prefix, &start_key)?));
+        }
+
+        let mut page = EventsPage {
+            events: Vec::new(),
+            continuation_token: None,
+        };
+        let mut header: Option<BlockHeader> = None;
+        for position in merge_positions(cf, candidates) {
+            let position = position?;
+            if position.block_number > to_block {
+                break;
+            }
+            let Some(event) = self.get_decoded::<StoredEvent>("event", &position.to_key())? else {
+                continue;
+            };
+            if !filter.matches(&event) {
+                continue;
+            }
+            if page.events.len() == chunk_size {
+                page.continuation_token = Some(position.to_string());
+                break;
+            }
+
+            if header.as_ref().map(|header| header.block_number) != Some(position.block_number) {
+                // the header is written in the same batch as the events of the block
+                header = Some(
+                    self.get_block_header(position.block_number)?
+                        .ok_or_else(|| invalid_entry("event", &position.to_key()))?,
+                );
+            }
+            page.events.push(EmittedEvent {
+                block_number: position.block_number,
+                block_hash: header
+                    .as_ref()
+                    .map(|header| header.block_hash)
+                    .unwrap_or_default(),
+                transaction_hash: event.transaction_hash,
+                from_address: event.from_address,
+                keys: event.keys,
+                data: event.data,
+            });
+        }
+        Ok(page)
+    }
+
+    /// Record a chain reorganization, keyed by `detection time || sequence`,
+    /// the sequence telling apart the reorgs detected in the same second.
+    pub fn insert_reorg(&self, reorg: &Reorg) -> Result<(), DatabaseError> {
+        let _guard = self.lock_writes();
+        let timestamp = reorg.timestamp.to_be_bytes();
+        let mut sequence = 0u64;
+        for item in self.iter_prefix("reorg", &timestamp)? {
+            item?;
+            sequence += 1;
+        }
+        let db_key = [timestamp, sequence.to_be_bytes()].concat();
+        let encoded = encode("reorg", &db_key, reorg)?;
+        self.insert("reorg", &db_key, &encoded)
+    }
+
+    /// All the recorded chain reorganizations, oldest first.
+    pub fn get_reorgs(&self) -> Result<Vec<Reorg>, DatabaseError> {
+        self.iter("reorg")?
+            .map(|item| {
+                let (key, encoded) = item?;
+                decode("reorg", &key, &encoded)
+            })
+            .collect()
+    }
+
+    /// Revert the database to block `index`, the last block kept.
+    ///
+    /// The state, the indexes, the headers and the sync cursor are reverted in
+    /// a single atomic write.
+    pub fn revert_to(&self, index: u64) -> Result<(), DatabaseError> {
+        let _guard = self.lock_writes();
+        let cursor = self.get_sync_cursor()?;
+        if cursor.is_some_and(|cursor| cursor.block_number <= index) {
+            return Ok(());
+        }
+        let mut batch = WriteBatch::default();
+
+        let change_sets = match cursor {
+            Some(cursor) => self.change_sets_after(index, cursor.block_number)?,
+            None => None,
+        };
+        match change_sets {
+            Some(change_sets) => self.revert_changes(&mut batch, index, &change_sets)?,
+            // some blocks were applied before change sets were recorded
+            None => self.revert_by_scan(&mut batch, index)?,
+        }
+
+        let cf_hash = self.cf("block_hash")?;
+        let from = (index + 1).to_be_bytes();
+        for item in self.iter_prefix_from("block", &[], &from)? {
+            let (key, encoded) = item?;
+            let header: BlockHeader = decode("block", &key, &encoded)?;
+            batch.delete_cf(cf_hash, header.block_hash.bytes());
+        }
+        self.delete_from(&mut batch, "block", &from)?;
+        self.delete_from(&mut batch, "state_diff", &from)?;
+        self.delete_from(&mut batch, "change_set", &from)?;
+
+        let cf_hash = self.cf("transaction_hash")?;
+        let cf_account = self.cf("account_transaction")?;
+        let from = transaction_key(index + 1, 0);
+        for item in self.iter_prefix_from("transaction", &[], &from)? {
+            let (key, encoded) = item?;
+            let transaction: Transaction = decode("transaction", &key, &encoded)?;
+            batch.delete_cf(cf_hash, transaction.transaction_hash.bytes());
+            if let Some((account, nonce)) = transaction.account_nonce() {
+                batch.delete_cf(cf_account, account_transaction_key(&account, &nonce));
+            }
+        }
+        self.delete_from(&mut batch, "transaction", &from)?;
+        self.delete_from(&mut batch, "receipt", &from)?;
+
+        let cf_address = self.cf("event_address")?;
+        let cf_key = self.cf("event_key")?;
+        let from = EventPosition::new(index + 1, 0, 0).to_key();
+        for item in self.iter_prefix_from("event", &[], &from)? {
+            let (key, encoded) = item?;
+            let event: StoredEvent = decode("event", &key, &encoded)?;
+            let position =
+                EventPosition::from_key(&key).ok_or_else(|| invalid_entry("event", &key))?;
+            let (address_key, first_key_key) = event_index_keys(&event, position);
+            batch.delete_cf(cf_address, address_key);
+            if let Some(first_key_key) = first_key_key {
+                batch.delete_cf(cf_key, first_key_key);
+            }
+        }
+        self.delete_from(&mut batch, "event", &from)?;
+
+        // move the sync cursor back so the next sync resumes after `index`
+        if cursor.is_some() {
+            let cursor = SyncCursor {
+                block_number: index,
+                block_hash: self
+                    .get_block_header(index)?
+                    .map(|header| header.block_hash),
+            };
+            let encoded = encode("meta", SYNC_CURSOR_KEY, &cursor)?;
+            batch.put_cf(self.cf("meta")?, SYNC_CURSOR_KEY, encoded);
+        }
+
+        self.write(batch, &format!("the revert to block {index}"))
+    }
+
+    /// Change sets of the blocks `index + 1..=last`, latest first. `None` if
+    /// one of them is missing.
+    fn change_sets_after(
+        &self,
+        index: u64,
+        last: u64,
+    ) -> Result<Option<Vec<ChangeSet>>, DatabaseError> {
+        let mut change_sets = Vec::new();
+        for block_number in (index + 1..=last).rev() {
+            match self.get_change_set(block_number)? {
+                Some(change_set) => change_sets.push(change_set),
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(change_sets))
+    }
+
+    /// Revert the state to `index`, visiting only the entries changed by the
+    /// blocks after it.
+    fn revert_changes(
+        &self,
+        batch: &mut WriteBatch,
+        index: u64,
+        change_sets: &[ChangeSet],
+    ) -> Result<(), DatabaseError> {
+        let slots: HashSet<_> = change_sets
+            .iter()
+            .flat_map(|changes| &changes.storage)
+            .map(|change| storage_key(&change.contract, &change.key))
+            .collect();
+        for db_key in slots {
+            self.revert_slot(batch, &db_key, index)?;
+        }
+
+        let cf = self.cf("contract")?;
+        let cf_usage = self.cf("class_usage")?;
+        let contracts: HashSet<_> = change_sets.iter().flat_map(ChangeSet::contracts).collect();
+        for address in contracts {
+            let Some(mut contract) = self.get_contract(address)? else {
+                continue;
+            };
+            let mut reverted = false;
+            for (from_block, class_hash) in contract.class_hash_history().range(index + 1, u64::MAX)
+            {
+                batch.delete_cf(cf_usage, class_usage_key(class_hash, &address, from_block));
+                reverted = true;
+            }
+            contract.revert_to(index);
+            if reverted {
+                // the class replaced after `index` is in use again
+                if let Some((from_block, class_hash)) = contract.class_hash_history().last() {
+                    let db_key = class_usage_key(class_hash, &address, from_block);
+                    let encoded = encode("class_usage", &db_key, &None::<u64>)?;
+                    batch.put_cf(cf_usage, db_key, encoded);
+                }
+            }
+            if contract.is_empty() {
+                batch.delete_cf(cf, address.bytes());
+            } else {
+                let encoded = encode("contract", address.bytes(), &contract)?;
+                batch.put_cf(cf, address.bytes(), encoded);
+            }
+        }
+
+        let cf = self.cf("class")?;
+        let classes: HashSet<_> = change_sets
+            .iter()
+            .flat_map(|changes| {
+                changes.declared_classes.iter().copied().chain(
+                    changes
+                        .compiled_class_hashes
+                        .iter()
+                        .map(|change| change.class_hash),
+                )
+            })
+            .collect();
+        for class_hash in classes {
+            let Some(mut class) = self.get_class(class_hash)? else {
+                continue;
+            };
+            if !class.is_declared_at(index) {
+                batch.delete_cf(cf, class_hash.bytes());
+            } else {
+                class.revert_to(index);
+                let encoded = encode("class", class_hash.bytes(), &class)?;
+                batch.put_cf(cf, class_hash.bytes(), encoded);
+            }
+        }
+        Ok(())
+    }
+
+    /// Add to `batch` the revert of a storage slot to `index`.
+    fn revert_slot(
+        &self,
+        batch: &mut WriteBatch,
+        db_key: &[u8],
+        index: u64,
+    ) -> Result<(), DatabaseError> {
+        match self.metadata.storage_layout {
+            StorageLayout::History => {
+                let Some(encoded) = self.get("key", db_key)? else {
+                    return Ok(());
+                };
+                let mut history: History<StarkFelt> = decode("key", db_key, &encoded)?;
+                history.revert_to(index);
+                let cf = self.cf("key")?;
+                if history.is_empty() {
+                    batch.delete_cf(cf, db_key);
+                } else {
+                    let encoded = encode("key", db_key, &history)?;
+                    batch.put_cf(cf, db_key, encoded);
+                }
+            }
+            StorageLayout::Flat => {
+                let cf = self.cf("storage")?;
+                let from = (index + 1).to_be_bytes();
+                for item in self.iter_prefix_from("storage", db_key, &from)? {
+                    let (change_key, _) = item?;
+                    batch.delete_cf(cf, change_key);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Revert the state to `index` by scanning every slot, contract and class.
+    fn revert_by_scan(&self, batch: &mut WriteBatch, index: u64) -> Result<(), DatabaseError> {
+        let cf = self.cf("storage")?;
+        for item in self.iter("storage")? {
+            let (change_key, _) = item?;
+            let block_number = decode_block(
+                "storage",
+                &change_key,
+                change_key.get(64..).unwrap_or_default(),
+            )?;
+            if block_number > index {
+                batch.delete_cf(cf, change_key);
+            }
+        }
+
+        let cf = self.cf("key")?;
+        for item in self.iter("key")? {
+            let (key, encoded) = item?;
+
+            let mut history: History<StarkFelt> = decode("key", &key, &encoded)?;
+
+            history.revert_to(index);
+            if history.is_empty() {
+                batch.delete_cf(cf, key);
+            } else {
+                let encoded = encode("key", &key, &history)?;
+                batch.put_cf(cf, key, encoded);
+            }
+        }
+
+        let cf = self.cf("contract")?;
+        for item in self.iter("contract")? {
+            let (key, encoded) = item?;
+
+            let mut contract: Contract = decode("contract", &key, &encoded)?;
+
+            contract.revert_to(index);
+            if contract.is_empty() {
+                batch.delete_cf(cf, key);
+            } else {
+                let encoded = encode("contract", &key, &contract)?;
+                batch.put_cf(cf, key, encoded);
+            }
+        }
+
+        let cf = self.cf("class")?;
+        for item in self.iter("class")? {
+            let (key, encoded) = item?;
+
+            let mut class: Class = decode("class", &key, &encoded)?;
+
+            if !class.is_declared_at(index) {
+                batch.delete_cf(cf, key);
+            } else {
+                class.revert_to(index);
+                let encoded = encode("class", &key, &class)?;
+                batch.put_cf(cf, key, encoded);
+            }
+        }
+
+        let cf = self.cf("class_usage")?;
+        for item in self.iter("class_usage")? {
+            let (key, encoded) = item?;
+            let (_, _, from_block) =
+                decode_class_usage_key(&key).ok_or_else(|| invalid_entry("class_usage", &key))?;
+            let to_block: Option<u64> = decode("class_usage", &key, &encoded)?;
+            if from_block > index {
+                batch.delete_cf(cf, key);
+            } else if to_block.is_some_and(|to_block| to_block >= index) {
+                // replaced after `index`: in use again
+                let encoded = encode("class_usage", &key, &None::<u64>)?;
+                batch.put_cf(cf, key, encoded);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Keys of an event in the `event_address` index (`address || position`) and,
+/// if it has keys, in the `event_key` index (`address || first key || position`).
+fn event_index_keys(event: &StoredEvent, position: EventPosition) -> (Vec<u8>, Option<Vec<u8>>) {
+    let position = position.to_key();
+    let address_key = [event.from_address.bytes(), &position].concat();
+    let first_key_key = event
+        .keys
+        .first()
+        .map(|first_key| [event.from_address.bytes(), first_key.bytes(), &position].concat());
+    (address_key, first_key_key)
+}
+
+/// Merge iterators over keys of `cf` ending with an event position, each
+/// sorted by position, into a single iterator over the positions in chain order.
+fn merge_positions<'a>(
+    cf: &'a str,
+    iterators: Vec<Box<dyn Iterator<Item = Result<Entry, DatabaseError>> + 'a>>,
+) -> impl Iterator<Item = Result<EventPosition, DatabaseError>> + 'a {
+    let position = move |item: Result<Entry, DatabaseError>| {
+        let (key, _) = item?;
+        key.len()
+            .checked_sub(24)
+            .and_then(|suffix| EventPosition::from_key(&key[suffix..]))
+            .ok_or_else(|| invalid_entry(cf, &key))
+    };
+    let mut iterators: Vec<_> = iterators
+        .into_iter()
+        .map(|iterator| iterator.map(position).peekable())
+        .collect();
+    std::iter::from_fn(move || {
+        let next = iterators
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(index, iterator)| match iterator.peek()? {
+                // report errors first
+                Err(_) => Some((index, None)),
+                Ok(position) => Some((index, Some(*position))),
+            })
+            .min_by_key(|(_, position)| *position)?
+            .0;
+        iterators[next].next()
+    })
+}
+
+/// Key of a transaction (and its receipt): `block number || index in block`,
+/// both big-endian so transactions are sorted by execution order.
+fn transaction_key(block_number: u64, index: u64) -> [u8; 16] {
+    let mut db_key = [0u8; 16];
+    db_key[..8].copy_from_slice(&block_number.to_be_bytes());
+    db_key[8..].copy_from_slice(&index.to_be_bytes());
+    db_key
+}
+
+/// Key of the `class_usage` index: `class hash || contract || from block`.
+fn class_usage_key(class_hash: &StarkFelt, contract: &StarkFelt, from_block: u64) -> Vec<u8> {
+    [
+        class_hash.bytes(),
+        contract.bytes(),
+        &from_block.to_be_bytes(),
+    ]
+    .concat()
+}
+
+fn decode_class_usage_key(key: &[u8]) -> Option<(StarkFelt, StarkFelt, u64)> {
+    if key.len() != 72 {
+        return None;
+    }
+    let class_hash = StarkFelt::new(key[..32].try_into().ok()?).ok()?;
+    let contract = StarkFelt::new(key[32..64].try_into().ok()?).ok()?;
+    let from_block = u64::from_be_bytes(key[64..].try_into().ok()?);
+    Some((class_hash, contract, from_block))
+}
+
+/// Key of a transaction in the `account_transaction` index: `account || nonce`,
+/// so the transactions of an account are sorted by nonce.
+fn account_transaction_key(account: &StarkFelt, nonce: &StarkFelt) -> Vec<u8> {
+    [account.bytes(), nonce.bytes()].concat()
+}
+
+/// Decode a location stored as a transaction key.
+fn transaction_location(db_key: &[u8]) -> Option<TransactionLocation> {
+    Some(TransactionLocation {
+        block_number: u64::from_be_bytes(db_key.get(..8)?.try_into().ok()?),
+        index: u64::from_be_bytes(db_key.get(8..16)?.try_into().ok()?),
+    })
+}
+
+/// Decode a bincode value of the entry `key` of `cf`.
+fn decode<T: DeserializeOwned>(cf: &str, key: &[u8], encoded: &[u8]) -> Result<T, DatabaseError> {
+    bincode::deserialize(encoded).map_err(|source| DatabaseError::DecodeError {
+        cf: cf.to_string(),
+        key: key.to_vec(),
+        source,
+    })
+}
+
+/// Encode with bincode the value of the entry `key` of `cf`.
+fn encode<T: Serialize + ?Sized>(
+    cf: &str,
+    key: &[u8],
+    value: &T,
+) -> Result<Vec<u8>, DatabaseError> {
+    bincode::serialize(value).map_err(|source| DatabaseError::EncodeError {
+        cf: cf.to_string(),
+        key: key.to_vec(),
+        source,
+    })
+}
+
+fn history_error(cf: &str, key: &[u8], source: HistoryError) -> DatabaseError {
+    DatabaseError::HistoryError {
+        cf: cf.to_string(),
+        key: key.to_vec(),
+        source,
+    }
+}
+
+fn rocksdb_error(cf: &str, key: &[u8], source: rocksdb::Error) -> DatabaseError {
+    DatabaseError::RocksDBError {
+        cf: cf.to_string(),
+        key: key.to_vec(),
+        source,
+    }
+}
+
+fn path_error(path: &str, source: rocksdb::Error) -> DatabaseError {
+    DatabaseError::PathError {
+        path: path.to_string(),
+        source,
+    }
+}
+
+fn iterator_error(cf: &str, source: rocksdb::Error) -> DatabaseError {
+    DatabaseError::IteratorError {
+        cf: cf.to_string(),
+        source,
+    }
+}
+
+fn invalid_entry(cf: &str, key: &[u8]) -> DatabaseError {
+    DatabaseError::InvalidEntry {
+        cf: cf.to_string(),
+        key: key.to_vec(),
+    }
+}
+
+/// Decode a big-endian block number, part of the entry `key` of `cf`.
+fn decode_block(cf: &str, key: &[u8], bytes: &[u8]) -> Result<u64, DatabaseError> {
+    let bytes = bytes.try_into().map_err(|_| invalid_entry(cf, key))?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+/// Decode a felt, part of the entry `key` of `cf`.
+fn decode_felt(cf: &str, key: &[u8], bytes: &[u8]) -> Result<StarkFelt, DatabaseError> {
+    bytes
+        .try_into()
+        .ok()
+        .and_then(|bytes| StarkFelt::new(bytes).ok())
+        .ok_or_else(|| invalid_entry(cf, key))
+}
+
+/// Raw key as shown in errors.
+fn hex(bytes: &[u8]) -> String {
+    let digits: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+    format!("0x{digits}")
+}
+
+/// Key of a storage slot in the `key` column, and prefix of its changes in
+/// the `storage` column: `contract || key`.
+fn storage_key(contract: &StarkFelt, key: &StarkFelt) -> Vec<u8> {
+    let mut db_key = Vec::with_capacity(64);
+    db_key.extend_from_slice(contract.bytes());
+    db_key.extend_from_slice(key.bytes());
+    db_key
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::state_update::DeployedContract;
+    use crate::transaction::{Event, TransactionType};
+
+    /// Temporary database removed on drop.
+    struct TestDatabase {
+        db: Option<Arc<Database>>,
+        path: std::path::PathBuf,
+    }
+
+    impl TestDatabase {
+        fn new(name: &str) -> Self {
+            TestDatabase::with_layout(name, StorageLayout::default())
+        }
+
+        fn with_layout(name: &str, storage_layout: StorageLayout) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("explorer-test-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&path);
+            let db = Database::new(path.to_str().unwrap(), None, storage_layout).unwrap();
+            TestDatabase {
+                db: Some(Arc::new(db)),
+                path,
+            }
+        }
+
+        fn db(&self) -> Arc<Database> {
+            self.db.clone().unwrap()
+        }
+    }
+
+    impl Drop for TestDatabase {
+        fn drop(&mut self) {
+            drop(self.db.take());
+            let _ = std::fs::remove_dir_all(&self.path);
+        }
+    }
+
+    /// Block `block_number` of a test chain, applying `state_diff`.
+    fn test_block(block_number: u64, state_diff: StateDiff) -> (Block, StateUpdate) {
+        let block_hash = StarkFelt::from(block_number + 1);
+        let block = Block {
+            block_hash,
+            parent_block_hash: StarkFelt::from(block_number),
+            block_number,
+            timestamp: block_number,
+            sequencer_address: None,
+            gas_price: None,
+            l1_gas_price: None,
+            starknet_version: None,
+            transactions: Vec::new(),
+            transaction_receipts: Vec::new(),
+        };
+        let state_update = StateUpdate {
+            block_hash,
+            new_root: StarkFelt::from(block_number + 1),
+            old_root: StarkFelt::from(block_number),
+            state_diff,
+        };
+        (block, state_update)
+    }
+
+    fn empty_state_diff() -> StateDiff {
+        StateDiff {
+            storage_diffs: HashMap::new(),
+            deployed_contracts: Vec::new(),
+            old_declared_contracts: Vec::new(),
+            declared_classes: Vec::new(),
+            nonces: HashMap::new(),
+            replaced_classes: Vec::new(),
+            migrated_compiled_classes: Vec::new(),
+        }
+    }
+
+    fn apply(db: &Database, block_number: u64, state_diff: StateDiff) {
+        let (block, state_update) = test_block(block_number, state_diff);
+        db.apply_block(block_number, &block, &state_update).unwrap();
+    }
+
+    fn entries(history: &History<StarkFelt>) -> Vec<(u64, StarkFelt)> {
+        history
+            .iter()
+            .map(|(index, value)| (index, *value))
+            .collect()
+    }
+
+    #[test]
+    fn apply_block_merges_the_updates_of_a_contract() {
+        let test_db = TestDatabase::new("apply-block-contract-updates");
+        let db = test_db.db();
+        let contract = StarkFelt::from(0x1234_u64);
+        let (class_0, class_1) = (StarkFelt::from(0xc0_u64), StarkFelt::from(0xc1_u64));
+
+        // deployed and sending its first transaction in the same block
+        let mut state_diff = empty_state_diff();
+        state_diff.deployed_contracts.push(DeployedContract {
+            address: contract,
+            class_hash: class_0,
+        });
+        state_diff.nonces.insert(contract, StarkFelt::from(1u64));
+        apply(&db, 0, state_diff);
+
+        let mut state_diff = empty_state_diff();
+        state_diff.replaced_classes.push(DeployedContract {
+            address: contract,
+            class_hash: class_1,
+        });
+        state_diff.nonces.insert(contract, StarkFelt::from(2u64));
+        apply(&db, 1, state_diff);
+
+        assert_eq!(
+            entries(&db.get_class_hash_history(contract).unwrap()),
+            [(0, class_0), (1, class_1)]
+        );
+        assert_eq!(
+            entries(&db.get_nonce_history(contract).unwrap()),
+            [(0, StarkFelt::from(1u64)), (1, StarkFelt::from(2u64))]
+        );
+        assert_eq!(db.get_class_hash_at(contract, 0).unwrap(), Some(class_0));
+        assert_eq!(
+            db.get_nonce_at(contract, 0).unwrap(),
+            Some(StarkFelt::from(1u64))
+        );
+    }
+
+    #[test]
+    fn apply_block_refuses_blocks_not_following_the_cursor() {
+        let test_db = TestDatabase::new("apply-block-contiguous");
+        let db = test_db.db();
+        for block_number in 0..3 {
+            apply(&db, block_number, empty_state_diff());
+        }
+
+        // a block fetched before a revert
+        db.revert_to(0).unwrap();
+        let (block, state_update) = test_block(2, empty_state_diff());
+        let result = db.apply_block(2, &block, &state_update);
+        assert!(matches!(
+            result,
+            Err(DatabaseError::NonContiguousBlock {
+                block_number: 2,
+                last: 0
+            })
+        ));
+        assert!(db.get_block_header(2).unwrap().is_none());
+        assert_eq!(db.get_sync_cursor().unwrap().unwrap().block_number, 0);
+
+        apply(&db, 1, empty_state_diff());
+        assert_eq!(db.get_sync_cursor().unwrap().unwrap().block_number, 1);
+    }
+
+    #[test]
+    fn concurrent_applies_and_reverts_keep_blocks_whole() {
+        const BLOCKS: u64 = 300;
+        let test_db = TestDatabase::new("concurrent-apply-revert");
+        let contract = StarkFelt::from(0x1234_u64);
+        let key = StarkFelt::from(0x1_u64);
+
+        // every block writes a slot and the nonce of the same contract
+        let applier = {
+            let db = test_db.db();
+            std::thread::spawn(move || {
+                let mut block_number = 0;
+                while block_number < BLOCKS {
+                    let mut state_diff = empty_state_diff();
+                    let value = StarkFelt::from(block_number + 1);
+                    state_diff
+                        .storage_diffs
+                        .insert(contract, vec![StorageDiff { key, value }]);
+                    state_diff.nonces.insert(contract, value);
+                    let (block, state_update) = test_block(block_number, state_diff);
+                    match db.apply_block(block_number, &block, &state_update) {
+                        Ok(()) => block_number += 1,
+                        // reverted meanwhile: resume after the cursor, as the syncer does
+                        Err(DatabaseError::NonContiguousBlock { last, .. }) => {
+                            block_number = last + 1
+                        }
+                        Err(e) => panic!("cannot apply block {block_number}: {e}"),
+                    }
+                }
+            })
+        };
+        let reverter = {
+            let db = test_db.db();
+            std::thread::spawn(move || {
+                for _ in 0..BLOCKS / 3 {
+                    if let Some(cursor) = db.get_sync_cursor().unwrap() {
+                        if let Some(index) = cursor.block_number.checked_sub(2) {
+                            db.revert_to(index).unwrap();
+                        }
+                    }
+                    std::thread::yield_now();
+                }
+            })
+        };
+        applier.join().unwrap();
+        reverter.join().unwrap();
+
+        // a block is either fully applied or fully reverted, with no gap
+        let db = test_db.db();
+        let cursor = db.get_sync_cursor().unwrap().unwrap();
+        assert!((0..=cursor.block_number)
+            .all(|block_number| db.get_block_header(block_number).unwrap().is_some()));
+        let expected: Vec<_> = (0..=cursor.block_number)
+            .map(|block_number| (block_number, StarkFelt::from(block_number + 1)))
+            .collect();
+        assert_eq!(
+            entries(&db.get_key_history(contract, key).unwrap()),
+            expected
+        );
+        assert_eq!(entries(&db.get_nonce_history(contract).unwrap()), expected);
+        assert!((cursor.block_number + 1..BLOCKS)
+            .all(|block_number| db.get_block_header(block_number).unwrap().is_none()));
+    }
+
+    /// Every entry of every column, but the metadata record.
+    fn dump(db: &Database) -> Vec<(&'static str, Entry)> {
+        const COLUMNS: [&str; 18] = [
+            "contract",
+            "key",
+            "meta",
+            "block",
+            "reorg",
+            "class",
+            "block_hash",
+            "transaction",
+            "receipt",
+            "event",
+            "event_address",
+            "event_key",
+            "transaction_hash",
+            "account_transaction",
+            "state_diff",
+            "class_usage",
+            "change_set",
+            "storage",
+        ];
+        let mut entries = Vec::new();
+        for cf in COLUMNS {
+            for item in db.iter(cf).unwrap() {
+                let (key, value) = item.unwrap();
+                if !(cf == "meta" && &*key == METADATA_KEY) {
+                    entries.push((cf, (key, value)));
+                }
+            }
+        }
+        entries
+    }
+
+    /// An invoke transaction of `sender` emitting an event, and its receipt.
+    fn invoke(block_number: u64, sender: StarkFelt, nonce: u64) -> (Transaction, Receipt) {
+        let transaction_hash = StarkFelt::from(0x1000 + block_number);
+        let transaction = Transaction {
+            transaction_hash,
+            r#type: TransactionType::InvokeFunction,
+            version: Some(StarkFelt::from(1u64)),
+            sender_address: Some(sender),
+            contract_address: None,
+            class_hash: None,
+            compiled_class_hash: None,
+            entry_point_selector: None,
+            nonce: Some(StarkFelt::from(nonce)),
+            max_fee: None,
+            tip: None,
+            contract_address_salt: None,
+            calldata: Vec::new(),
+            constructor_calldata: Vec::new(),
+            signature: Vec::new(),
+        };
+        let receipt = Receipt {
+            transaction_hash,
+            transaction_index: 0,
+            actual_fee: None,
+            execution_status: None,
+            revert_error: None,
+            events: vec![Event {
+                from_address: sender,
+                keys: vec![StarkFelt::from(0xe_u64)],
+                data: vec![StarkFelt::from(block_number)],
+            }],
+            l2_to_l1_messages: Vec::new(),
+            execution_resources: None,
+        };
+        (transaction, receipt)
+    }
+
+    /// Blocks `0..5` deploying, replacing, declaring and writing to contracts,
+    /// with a transaction in most blocks.
+    fn revert_test_chain() -> Vec<(Block, StateUpdate)> {
+        let felt = StarkFelt::from;
+        let (a, b, c) = (felt(0xa_u64), felt(0xb_u64), felt(0xcc_u64));
+        let (class_0, class_1, class_2) = (felt(0xc0_u64), felt(0xc1_u64), felt(0xc2_u64));
+        let (key_1, key_2, key_3) = (felt(0x1_u64), felt(0x2_u64), felt(0x3_u64));
+        let deploy = |address, class_hash| DeployedContract {
+            address,
+            class_hash,
+        };
+        let declare = |class_hash, compiled_class_hash| DeclaredClass {
+            class_hash,
+            compiled_class_hash,
+        };
+        let write = |key, value: u64| StorageDiff {
+            key,
+            value: felt(value),
+        };
+
+        let mut diffs: Vec<StateDiff> = (0..5).map(|_| empty_state_diff()).collect();
+        diffs[0].deployed_contracts = vec![deploy(a, class_0), deploy(b, class_0)];
+        diffs[0].declared_classes = vec![declare(class_0, felt(0xd0_u64))];
+        diffs[0].old_declared_contracts = vec![felt(0xe0_u64)];
+        diffs[0]
+            .storage_diffs
+            .insert(a, vec![write(key_1, 1), write(key_2, 2)]);
+        diffs[0].nonces.insert(a, felt(1u64));
+
+        diffs[1].storage_diffs.insert(a, vec![write(key_1, 3)]);
+        diffs[1].nonces.insert(a, felt(2u64));
+
+        diffs[2].storage_diffs.insert(b, vec![write(key_1, 5)]);
+        diffs[2].declared_classes = vec![declare(class_1, felt(0xd1_u64))];
+
+        diffs[3].replaced_classes = vec![deploy(a, class_1)];
+        diffs[3].deployed_contracts = vec![deploy(c, class_1)];
+        diffs[3].migrated_compiled_classes = vec![declare(class_0, felt(0xd2_u64))];
+        diffs[3].old_declared_contracts = vec![felt(0xe1_u64)];
+        diffs[3]
+            .storage_diffs
+            .insert(a, vec![write(key_1, 0), write(key_3, 7)]);
+        diffs[3].nonces.insert(a, felt(3u64));
+
+        diffs[4].storage_diffs.insert(b, vec![write(key_1, 6)]);
+        diffs[4].declared_classes = vec![declare(class_2, felt(0xd3_u64))];
+        diffs[4].nonces.insert(b, felt(1u64));
+
+        let senders = [Some((a, 0)), Some((a, 1)), None, Some((a, 2)), Some((b, 0))];
+        diffs
+            .into_iter()
+            .zip(senders)
+            .enumerate()
+            .map(|(block_number, (state_diff, sender))| {
+                let block_number = block_number as u64;
+                let (mut block, state_update) = test_block(block_number, state_diff);
+                if let Some((sender, nonce)) = sender {
+                    let (transaction, receipt) = invoke(block_number, sender, nonce);
+                    block.transactions.push(transaction);
+                    block.transaction_receipts.push(receipt);
+                }
+                (block, state_update)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn revert_matches_a_database_synced_to_the_same_block() {
+        const REVERT_TO: u64 = 2;
+        let chain = revert_test_chain();
+        let apply_chain = |db: &Database, last: u64| {
+            for (block, state_update) in &chain[..=last as usize] {
+                db.apply_block(block.block_number, block, state_update)
+                    .unwrap();
+            }
+        };
+
+        let synced_db = TestDatabase::new("revert-synced");
+        apply_chain(&synced_db.db(), REVERT_TO);
+        let expected = dump(&synced_db.db());
+
+        // from the change sets
+        let reverted_db = TestDatabase::new("revert-change-sets");
+        let db = reverted_db.db();
+        apply_chain(&db, 4);
+        db.revert_to(REVERT_TO).unwrap();
+        assert_eq!(dump(&db), expected);
+
+        // the class replaced after the revert point is in use again
+        let usage = db.get_class_usage(StarkFelt::from(0xc0_u64)).unwrap();
+        assert!(usage.iter().all(|usage| usage.to_block.is_none()));
+        assert_eq!(usage.len(), 2);
+
+        // by scanning, when a change set is missing
+        let scanned_db = TestDatabase::new("revert-scan");
+        let db = scanned_db.db();
+        apply_chain(&db, 4);
+        db.db
+            .delete_cf(db.cf("change_set").unwrap(), 3u64.to_be_bytes())
+            .unwrap();
+        db.revert_to(REVERT_TO).unwrap();
+        assert_eq!(dump(&db), expected);
+    }
+
+    /// Slot values, slot histories and storage dumps, see `storage_reads`.
+    type StorageReads = (
+        Vec<Option<StarkFelt>>,
+        Vec<Vec<(u64, StarkFelt)>>,
+        Vec<Vec<(StarkFelt, StarkFelt)>>,
+    );
+
+    /// Everything read from the storage of `contracts`: the value of each
+    /// slot at each block, the history of each slot and the storage dump of
+    /// each contract at each block, from several keys.
+    fn storage_reads(
+        db: &Database,
+        contracts: &[StarkFelt],
+        keys: &[StarkFelt],
+        blocks: std::ops::RangeInclusive<u64>,
+    ) -> StorageReads {
+        let mut values = Vec::new();
+        let mut histories = Vec::new();
+        let mut dumps = Vec::new();
+        for contract in contracts {
+            for key in keys {
+                for block_number in blocks.clone() {
+                    values.push(db.get_key_at(*contract, *key, block_number).unwrap());
+                }
+                values.push(db.get_key(*contract, *key).unwrap());
+                histories.push(entries(&db.get_key_history(*contract, *key).unwrap()));
+            }
+            for block_number in blocks.clone() {
+                for from_key in [keys[0], keys[keys.len() / 2]] {
+                    let slots = db.storage_at(*contract, block_number, from_key).unwrap();
+                    dumps.push(slots.collect::<Result<Vec<_>, _>>().unwrap());
+                }
+            }
+        }
+        (values, histories, dumps)
+    }
+
+    #[test]
+    fn flat_and_history_layouts_read_the_same_storage() {
+        const BLOCKS: u64 = 8;
+        let contracts = [StarkFelt::from(0xa_u64), StarkFelt::from(0xb_u64)];
+        let keys: Vec<_> = (0..8u64).map(StarkFelt::from).collect();
+        // overwrites, zeroes and slots skipped by some blocks
+        let state_diff = |block_number: u64| {
+            let mut state_diff = empty_state_diff();
+            for (i, contract) in contracts.iter().enumerate() {
+                let writes = (1..7u64)
+                    .filter(|key| (block_number + key + i as u64) % 3 != 1)
+                    .map(|key| StorageDiff {
+                        key: StarkFelt::from(key),
+                        value: StarkFelt::from((block_number * 7 + key) % 4),
+                    })
+                    .collect();
+                state_diff.storage_diffs.insert(*contract, writes);
+            }
+            state_diff
+        };
+
+        let history_db = TestDatabase::with_layout("layout-history", StorageLayout::History);
+        let flat_db = TestDatabase::with_layout("layout-flat", StorageLayout::Flat);
+        let (history_db, flat_db) = (history_db.db(), flat_db.db());
+        for block_number in 0..BLOCKS {
+            apply(&history_db, block_number, state_diff(block_number));
+            apply(&flat_db, block_number, state_diff(block_number));
+        }
+        let reads = storage_reads(&history_db, &contracts, &keys, 0..=BLOCKS);
+        assert!(reads.2.iter().any(|slots| !slots.is_empty()));
+        assert_eq!(
+            storage_reads(&flat_db, &contracts, &keys, 0..=BLOCKS),
+            reads
+        );
+
+        history_db.revert_to(3).unwrap();
+        flat_db.revert_to(3).unwrap();
+        assert_eq!(
+            storage_reads(&flat_db, &contracts, &keys, 0..=BLOCKS),
+            storage_reads(&history_db, &contracts, &keys, 0..=BLOCKS)
+        );
+
+        // a slot cannot be written twice at the same block
+        for db in [&history_db, &flat_db] {
+            let mut batch = WriteBatch::default();
+            let result = db.put_storage(&mut batch, contracts[0], keys[2], 3, keys[1]);
+            assert!(matches!(
+                result,
+                Err(DatabaseError::HistoryError {
+                    source: HistoryError::NonMonotonicIndex {
+                        last: 3,
+                        attempted: 3
+                    },
+                    ..
+                })
+            ));
+        }
+    }
+
+    #[test]
+    fn migrate_refuses_missing_and_empty_databases() {
+        let missing = std::env::temp_dir().join(format!(
+            "explorer-test-migrate-missing-{}",
+            std::process::id()
+        ));
+        let result = Database::migrate(missing.to_str().unwrap(), Some(Network::default()));
+        assert!(matches!(result, Err(DatabaseError::NotFound(_))));
+        assert!(!missing.exists());
+
+        // an existing directory holding no data
+        let empty = missing.with_extension("empty");
+        std::fs::create_dir_all(&empty).unwrap();
+        let result = Database::migrate(empty.to_str().unwrap(), Some(Network::default()));
+        assert!(matches!(result, Err(DatabaseError::NotFound(_))));
+        let _ = std::fs::remove_dir_all(&empty);
+    }
+
+    /// Strip a database down to what was written before schema versions were
+    /// recorded: the contract and storage histories.
+    fn strip_to_baseline(db: &Database) {
+        let mut batch = WriteBatch::default();
+        for (cf, (key, _)) in dump(db) {
+            if cf != "contract" && cf != "key" {
+                batch.delete_cf(db.cf(cf).unwrap(), key);
+            }
+        }
+        batch.delete_cf(db.cf("meta").unwrap(), METADATA_KEY);
+        db.db.write(batch).unwrap();
+    }
+
+    /// Closed database holding `revert_test_chain` as written before schema
+    /// versions were recorded.
+    fn baseline_database(name: &str, storage_layout: StorageLayout) -> TestDatabase {
+        let mut test_db = TestDatabase::with_layout(name, storage_layout);
+        let db = test_db.db();
+        for (block_number, (block, state_update)) in revert_test_chain().iter().enumerate() {
+            db.apply_block(block_number as u64, block, state_update)
+                .unwrap();
+        }
+        strip_to_baseline(&db);
+        drop(db);
+        drop(test_db.db.take());
+        test_db
+    }
+
+    #[test]
+    fn migrate_requires_the_network_of_a_database_without_metadata() {
+        let test_db = baseline_database("migrate-network", StorageLayout::History);
+        let path = test_db.path.to_str().unwrap();
+
+        let result = Database::migrate(path, None);
+        assert!(matches!(result, Err(DatabaseError::NetworkRequired)));
+        let result = Database::new(path, None, StorageLayout::default());
+        assert!(matches!(result, Err(DatabaseError::OutdatedSchema(0))));
+
+        assert_eq!(Database::migrate(path, Some(Network::Sepolia)).unwrap(), 0);
+        let db = Database::open_existing(path, None).unwrap();
+        assert_eq!(db.metadata().network, Network::Sepolia);
+    }
+
+    #[test]
+    fn migrate_resumes_after_the_last_block_of_a_database_without_cursor() {
+        for storage_layout in [StorageLayout::History, StorageLayout::Flat] {
+            let name = format!("migrate-cursor-{storage_layout:?}");
+            let test_db = baseline_database(&name, storage_layout);
+            let path = test_db.path.to_str().unwrap();
+            Database::migrate(path, Some(Network::default())).unwrap();
+
+            let db = Database::open_existing(path, None).unwrap();
+            let cursor = db.get_sync_cursor().unwrap().unwrap();
+            assert_eq!(cursor.block_number, 4);
+            assert!(cursor.block_hash.is_none());
+            let (block, state_update) = &revert_test_chain()[0];
+            let result = db.apply_block(0, block, state_update);
+            assert!(matches!(
+                result,
+                Err(DatabaseError::NonContiguousBlock { .. })
+            ));
+            apply(&db, 5, empty_state_diff());
+        }
+    }
+
+    #[test]
+    fn open_existing_refuses_missing_and_empty_databases() {
+        let missing =
+            std::env::temp_dir().join(format!("explorer-test-open-missing-{}", std::process::id()));
+        let result = Database::open_existing(missing.to_str().unwrap(), None);
+        assert!(matches!(result, Err(DatabaseError::NotFound(_))));
+        assert!(!missing.exists());
+
+        let empty = missing.with_extension("empty");
+        std::fs::create_dir_all(&empty).unwrap();
+        let result = Database::open_existing(empty.to_str().unwrap(), None);
+        assert!(matches!(result, Err(DatabaseError::NotFound(_))));
+        let _ = std::fs::remove_dir_all(&empty);
+
+        let mut test_db = TestDatabase::new("open-existing");
+        apply(&test_db.db(), 0, empty_state_diff());
+        drop(test_db.db.take());
+        let db = Database::open_existing(test_db.path.to_str().unwrap(), None).unwrap();
+        assert_eq!(db.get_sync_cursor().unwrap().unwrap().block_number, 0);
+    }
+
+    #[test]
+    fn reorgs_detected_in_the_same_second_are_all_recorded() {
+        let test_db = TestDatabase::new("same-second-reorgs");
+        let db = test_db.db();
+        for detected_at in [10, 20] {
+            let reorg = Reorg {
+                timestamp: 1_700_000_000,
+                detected_at,
+                common_ancestor: detected_at - 1,
+                orphaned: Vec::new(),
+            };
+            db.insert_reorg(&reorg).unwrap();
+        }
+
+        let detected_at: Vec<_> = db
+            .get_reorgs()
+            .unwrap()
+            .iter()
+            .map(|reorg| reorg.detected_at)
+            .collect();
+        assert_eq!(detected_at, [10, 20]);
+    }
+
+    /// Events `(block, transaction hash, address, keys, data)` as returned by
+    /// `get_events`.
+    type Events = Vec<(u64, StarkFelt, StarkFelt, Vec<StarkFelt>, Vec<StarkFelt>)>;
+
+    fn emitted(events: Vec<EmittedEvent>) -> Events {
+        events
+            .into_iter()
+            .map(|event| {
+                (
+                    event.block_number,
+                    event.transaction_hash,
+                    event.from_address,
+                    event.keys,
+                    event.data,
+                )
+            })
+            .collect()
+    }
+
+    /// Page through the events matching `filter`, checking the size of the pages.
+    fn events_by_page(db: &Database, filter: &EventFilter, chunk_size: usize) -> Events {
+        let mut events = Vec::new();
+        let mut continuation = None;
+        loop {
+            let page = db.get_events(filter, continuation, chunk_size).unwrap();
+            assert!(page.events.len() <= chunk_size);
+            let Some(token) = page.continuation_token else {
+                events.extend(emitted(page.events));
+                return events;
+            };
+            assert_eq!(page.events.len(), chunk_size);
+            events.extend(emitted(page.events));
+            continuation = Some(token.parse().unwrap());
+        }
+    }
+
+    #[test]
+    fn paginated_events_match_a_single_query() {
+        let felt = StarkFelt::from;
+        let (a, b) = (felt(0xa_u64), felt(0xb_u64));
+        let first_keys = [felt(0x1_u64), felt(0x2_u64), felt(0x3_u64)];
+        let second_keys = [felt(0x10_u64), felt(0x11_u64)];
+
+        let test_db = TestDatabase::new("events");
+        let db = test_db.db();
+        let mut stored = Vec::new();
+        for block_number in 0..5u64 {
+            let (mut block, state_update) = test_block(block_number, empty_state_diff());
+            for index in 0..3u64 {
+                let (mut transaction, mut receipt) = invoke(block_number, a, index);
+                transaction.transaction_hash = felt(0x1000 + block_number * 10 + index);
+                receipt.transaction_hash = transaction.transaction_hash;
+                receipt.transaction_index = index;
+                receipt.events = (0..3u64)
+                    .map(|event_index| {
+                        let n = block_number + index + event_index;
+                        let keys = match n % 5 {
+                            0 => Vec::new(),
+                            _ => vec![
+                                first_keys[((n + index) % 3) as usize],
+                                second_keys[((index + event_index) % 2) as usize],
+                            ],
+                        };
+                        Event {
+                            from_address: [a, b][(n % 2) as usize],
+                            keys,
+                            data: vec![felt(block_number * 100 + index * 10 + event_index)],
+                        }
+                    })
+                    .collect();
+                for event in &receipt.events {
+                    let event = StoredEvent {
+                        transaction_hash: receipt.transaction_hash,
+                        from_address: event.from_address,
+                        keys: event.keys.clone(),
+                        data: event.data.clone(),
+                    };
+                    stored.push((block_number, event));
+                }
+                block.transactions.push(transaction);
+                block.transaction_receipts.push(receipt);
+            }
+            db.apply_block(block_number, &block, &state_update).unwrap();
+        }
+
+        let filters = [
+            // several first keys merged from the `event_key` index
+            EventFilter {
+                address: Some(a),
+                keys: vec![vec![first_keys[0], first_keys[2]]],
+                ..EventFilter::default()
+            },
+            EventFilter {
+                address: Some(a),
+                keys: vec![vec![first_keys[0], first_keys[1]], vec![second_keys[0]]],
+                ..EventFilter::default()
+            },
+            EventFilter {
+                from_block: 1,
+                to_block: Some(3),
+                address: Some(b),
+                keys: vec![first_keys.to_vec()],
+            },
+            // the `event_address` index
+            EventFilter {
+                address: Some(a),
+                ..EventFilter::default()
+            },
+            EventFilter {
+                to_block: Some(2),
+                address: Some(b),
+                keys: vec![Vec::new(), vec![second_keys[1]]],
+                ..EventFilter::default()
+            },
+        ];
+        for filter in &filters {
+            let expected: Events = stored
+                .iter()
+                .filter(|(block_number, event)| {
+                    *block_number >= filter.from_block
+                        && *block_number <= filter.to_block.unwrap_or(u64::MAX)
+                        && filter.matches(event)
+                })
+                .map(|(block_number, event)| {
+                    (
+                        *block_number,
+                        event.transaction_hash,
+                        event.from_address,
+                        event.keys.clone(),
+                        event.data.clone(),
+                    )
+                })
+                .collect();
+            assert!(expected.len() > 3, "{filter:?}");
+
+            let all = db.get_events(filter, None, usize::MAX).unwrap();
+            assert!(all.continuation_token.is_none());
+            assert_eq!(emitted(all.events), expected, "{filter:?}");
+            for chunk_size in 1..=4 {
+                assert_eq!(
+                    events_by_page(&db, filter, chunk_size),
+                    expected,
+                    "{filter:?}, {chunk_size} per page"
+                );
+            }
+        }
     }
 }
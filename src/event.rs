@@ -0,0 +1,131 @@
+use serde::{Deserialize, Serialize};
+use starknet_api::hash::StarkFelt;
+
+/// Position of an event in the chain, also used as continuation token.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct EventPosition {
+    pub block_number: u64,
+    pub transaction_index: u64,
+    pub event_index: u64,
+}
+
+/// An event stored in the database, at the position given by its key.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StoredEvent {
+    pub transaction_hash: StarkFelt,
+    pub from_address: StarkFelt,
+    pub keys: Vec<StarkFelt>,
+    pub data: Vec<StarkFelt>,
+}
+
+/// An event matching a filter, as returned by `starknet_getEvents`.
+#[derive(Serialize, Debug, Clone)]
+pub struct EmittedEvent {
+    pub block_number: u64,
+    pub block_hash: StarkFelt,
+    pub transaction_hash: StarkFelt,
+    pub from_address: StarkFelt,
+    pub keys: Vec<StarkFelt>,
+    pub data: Vec<StarkFelt>,
+}
+
+/// Filter of `starknet_getEvents`.
+#[derive(Debug, Clone, Default)]
+pub struct EventFilter {
+    pub from_block: u64,
+    /// Last block included, the latest block when `None`.
+    pub to_block: Option<u64>,
+    pub address: Option<StarkFelt>,
+    /// Accepted values of each key: `keys[i]` lists the values accepted for
+    /// the i-th key of the event, an empty list accepts any value.
+    pub keys: Vec<Vec<StarkFelt>>,
+}
+
+/// A page of events, with the token to fetch the next one if there are more.
+#[derive(Serialize, Debug, Clone)]
+pub struct EventsPage {
+    pub events: Vec<EmittedEvent>,
+    pub continuation_token: Option<String>,
+}
+
+impl EventPosition {
+    pub fn new(block_number: u64, transaction_index: u64, event_index: u64) -> Self {
+        EventPosition {
+            block_number,
+            transaction_index,
+            event_index,
+        }
+    }
+
+    /// Database key, ordered as the events of the chain.
+    pub fn to_key(self) -> [u8; 24] {
+        let mut key = [0u8; 24];
+        key[..8].copy_from_slice(&self.block_number.to_be_bytes());
+        key[8..16].copy_from_slice(&self.transaction_index.to_be_bytes());
+        key[16..].copy_from_slice(&self.event_index.to_be_bytes());
+        key
+    }
+
+    pub fn from_key(key: &[u8]) -> Option<Self> {
+        if key.len() != 24 {
+            return None;
+        }
+        let number =
+            |range: std::ops::Range<usize>| Some(u64::from_be_bytes(key[range].try_into().ok()?));
+        Some(EventPosition::new(
+            number(0..8)?,
+            number(8..16)?,
+            number(16..24)?,
+        ))
+    }
+}
+
+impl std::fmt::Display for EventPosition {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}-{}-{}",
+            self.block_number, self.transaction_index, self.event_index
+        )
+    }
+}
+
+impl std::str::FromStr for EventPosition {
+    type Err = String;
+
+    /// Parse a continuation token, formatted as `block-transaction-event`.
+    fn from_str(token: &str) -> Result<Self, Self::Err> {
+        let parts = token
+            .split('-')
+            .map(str::parse::<u64>)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| format!("Invalid continuation token: {token}"))?;
+        match parts[..] {
+            [block_number, transaction_index, event_index] => Ok(EventPosition::new(
+                block_number,
+                transaction_index,
+                event_index,
+            )),
+            _ => Err(format!("Invalid continuation token: {token}")),
+        }
+    }
+}
+
+impl EventFilter {
+    pub fn matches(&self, event: &StoredEvent) -> bool {
+        if self
+            .address
+            .is_some_and(|address| address != event.from_address)
+        {
+            return false;
+        }
+        self.keys
+            .iter()
+            .enumerate()
+            .all(|(index, accepted)| match event.keys.get(index) {
+                _ if accepted.is_empty() => true,
+                Some(key) => accepted.contains(key),
+                None => false,
+            })
+    }
+}
@@ -0,0 +1,270 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use serde::{de::DeserializeOwned, Deserialize};
+use starknet_api::hash::StarkFelt;
+
+use crate::config::GatewayConfig;
+use crate::state_update::StateUpdateWithBlock;
+
+/// Client of the Starknet feeder gateway.
+///
+/// Requests are rate limited by a token bucket whose rate is halved each time
+/// the gateway answers `429 Too Many Requests` and slowly restored on success.
+/// Transient failures (throttling, server errors, connection errors) are
+/// retried with a jittered exponential backoff, permanent ones are returned
+/// right away.
+pub struct GatewayClient {
+    client: reqwest::Client,
+    base_url: String,
+    limiter: Mutex<TokenBucket>,
+    max_attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum GatewayError {
+    #[error("Block not found: {0}")]
+    BlockNotFound(String),
+    #[error("Request rejected with status {status}: {body}")]
+    Rejected {
+        status: reqwest::StatusCode,
+        body: String,
+    },
+    #[error("Invalid response: {0}")]
+    Decode(serde_json::Error),
+    #[error("Giving up after {attempts} attempts, last error: {last}")]
+    MaxAttempts { attempts: u32, last: String },
+}
+
+/// Block to request from the feeder gateway.
+#[derive(Debug, Clone, Copy)]
+pub enum BlockId {
+    Number(u64),
+    Latest,
+}
+
+/// Header of a block, as returned by `get_block?headerOnly=true`.
+#[derive(Deserialize, Debug)]
+pub struct GatewayBlockHeader {
+    pub block_number: u64,
+    pub block_hash: StarkFelt,
+}
+
+/// Error returned by the feeder gateway in the body of a failed request.
+#[derive(Deserialize, Debug)]
+struct StarknetError {
+    code: String,
+}
+
+/// Outcome of a single attempt.
+enum Attempt<T> {
+    Done(T),
+    /// Transient failure, retried after the given delay (or the backoff delay).
+    Retry(String, Option<Duration>),
+}
+
+impl std::fmt::Display for BlockId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BlockId::Number(number) => write!(f, "{}", number),
+            BlockId::Latest => write!(f, "latest"),
+        }
+    }
+}
+
+impl GatewayClient {
+    pub fn new(base_url: &str, config: &GatewayConfig, max_attempts: u32) -> Self {
+        GatewayClient {
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(config.timeout_secs))
+                .build()
+                .unwrap_or_default(),
+            base_url: base_url.to_string(),
+            limiter: Mutex::new(TokenBucket::new(
+                config.requests_per_second,
+                config.burst as f64,
+            )),
+            max_attempts,
+            base_delay: Duration::from_millis(config.retry_base_delay_ms),
+            max_delay: Duration::from_millis(config.retry_max_delay_ms),
+        }
+    }
+
+    /// State update of a block, together with the block and its transactions.
+    pub async fn get_state_update_with_block(
+        &self,
+        block_number: u64,
+    ) -> Result<StateUpdateWithBlock, GatewayError> {
+        let url = format!(
+            "{}/get_state_update?blockNumber={}&includeBlock=true",
+            self.base_url, block_number
+        );
+        self.get(&url).await
+    }
+
+    pub async fn get_block_header(
+        &self,
+        block_id: BlockId,
+    ) -> Result<GatewayBlockHeader, GatewayError> {
+        let url = format!(
+            "{}/get_block?blockNumber={}&headerOnly=true",
+            self.base_url, block_id
+        );
+        self.get(&url).await
+    }
+
+    async fn get<T: DeserializeOwned>(&self, url: &str) -> Result<T, GatewayError> {
+        let mut attempts = 0;
+        loop {
+            self.wait_for_token().await;
+            attempts += 1;
+
+            let (reason, retry_after) = match self.attempt(url).await? {
+                Attempt::Done(value) => {
+                    self.limiter().on_success();
+                    return Ok(value);
+                }
+                Attempt::Retry(reason, retry_after) => (reason, retry_after),
+            };
+
+            if attempts > self.max_attempts {
+                return Err(GatewayError::MaxAttempts {
+                    attempts,
+                    last: reason,
+                });
+            }
+            let delay = retry_after
+                .map(|delay| delay.min(self.max_delay))
+                .unwrap_or_else(|| self.backoff(attempts));
+            log::info!("{reason}, retrying {url} in {delay:?}...");
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    async fn attempt<T: DeserializeOwned>(&self, url: &str) -> Result<Attempt<T>, GatewayError> {
+        let response = match self.client.get(url).send().await {
+            Ok(response) => response,
+            // connection refused or reset, timeout...
+            Err(e) => return Ok(Attempt::Retry(format!("send fail: {e}"), None)),
+        };
+
+        let status = response.status();
+        let retry_after = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<u64>().ok())
+            .map(Duration::from_secs);
+        let body = match response.text().await {
+            Ok(body) => body,
+            Err(e) => return Ok(Attempt::Retry(format!("read fail: {e}"), None)),
+        };
+
+        match status {
+            reqwest::StatusCode::OK => serde_json::from_str(&body)
+                .map(Attempt::Done)
+                .map_err(GatewayError::Decode),
+            reqwest::StatusCode::TOO_MANY_REQUESTS => {
+                self.limiter().on_throttled();
+                Ok(Attempt::Retry("Too many requests".into(), retry_after))
+            }
+            reqwest::StatusCode::REQUEST_TIMEOUT => Ok(Attempt::Retry(
+                format!("code status: {status}"),
+                retry_after,
+            )),
+            status if status.is_server_error() => Ok(Attempt::Retry(
+                format!("code status: {status}"),
+                retry_after,
+            )),
+            status => match serde_json::from_str::<StarknetError>(&body) {
+                Ok(error) if error.code == "StarknetErrorCode.BLOCK_NOT_FOUND" => {
+                    Err(GatewayError::BlockNotFound(body))
+                }
+                _ => Err(GatewayError::Rejected { status, body }),
+            },
+        }
+    }
+
+    /// Exponential backoff capped to the maximum delay, jittered between half
+    /// and the full delay so that concurrent retries spread out.
+    fn backoff(&self, attempts: u32) -> Duration {
+        let exponential = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempts.saturating_sub(1)))
+            .min(self.max_delay);
+        let millis = exponential.as_millis() as u64;
+        Duration::from_millis(rand::thread_rng().gen_range(millis / 2..=millis))
+    }
+
+    async fn wait_for_token(&self) {
+        let wait = self.limiter().reserve();
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    fn limiter(&self) -> std::sync::MutexGuard<'_, TokenBucket> {
+        self.limiter
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Token bucket with an adaptive refill rate.
+struct TokenBucket {
+    /// Current refill rate, in tokens per second.
+    rate: f64,
+    /// Configured refill rate, never exceeded.
+    max_rate: f64,
+    capacity: f64,
+    /// Available tokens, negative when requests are waiting for a token.
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    /// Slowest rate the limiter slows down to.
+    const MIN_RATE: f64 = 0.1;
+
+    fn new(rate: f64, capacity: f64) -> Self {
+        let rate = rate.max(Self::MIN_RATE);
+        let capacity = capacity.max(1.0);
+        TokenBucket {
+            rate,
+            max_rate: rate,
+            capacity,
+            tokens: capacity,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    /// Take a token, returning how long to wait until it is actually available.
+    fn reserve(&mut self) -> Duration {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.refilled_at = now;
+
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+
+    fn on_throttled(&mut self) {
+        self.rate = (self.rate / 2.0).max(Self::MIN_RATE);
+        log::warn!(
+            "Throttled by the gateway, slowing down to {:.1} requests/s",
+            self.rate
+        );
+    }
+
+    fn on_success(&mut self) {
+        self.rate = (self.rate + self.max_rate / 100.0).min(self.max_rate);
+    }
+}
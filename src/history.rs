@@ -1,10 +1,16 @@
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 /// A simple history implementation that stores values at a given index.
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 #[serde(bound = "T: Serialize + DeserializeOwned")]
 pub struct History<T>(Vec<(u64, T)>);
 
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryError {
+    #[error("index {attempted} is not after the last index {last}")]
+    NonMonotonicIndex { last: u64, attempted: u64 },
+}
+
 /// A simple history implementation that stores values at a given index.
 /// It allows to get the value at a given index, push a new value with an index,
 /// and revert the history to a given index.
@@ -20,9 +26,12 @@ where
 
     /// Push a new value with an index.
     /// If the index is smaller or equal to the last index, it will return an error.
-    pub fn push(&mut self, index: u64, value: T) -> Result<(), ()> {
+    pub fn push(&mut self, index: u64, value: T) -> Result<(), HistoryError> {
         match self.0.last() {
-            Some((last_index, _)) if index <= *last_index => Err(()),
+            Some((last, _)) if index <= *last => Err(HistoryError::NonMonotonicIndex {
+                last: *last,
+                attempted: index,
+            }),
             _ => {
                 self.0.push((index, value));
                 Ok(())
@@ -57,6 +66,35 @@ where
         }
     }
 
+    /// Iterate over the `(index, value)` entries, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = (u64, &T)> {
+        self.0.iter().map(|(index, value)| (*index, value))
+    }
+
+    /// Iterate over the entries whose index is in `from..=to`, oldest first.
+    pub fn range(&self, from: u64, to: u64) -> impl Iterator<Item = (u64, &T)> {
+        let start = self.0.partition_point(|&(i, _)| i < from);
+        let end = self.0.partition_point(|&(i, _)| i <= to).max(start);
+        self.0[start..end]
+            .iter()
+            .map(|(index, value)| (*index, value))
+    }
+
+    /// First entry, the value set at the lowest index.
+    pub fn first(&self) -> Option<(u64, &T)> {
+        self.0.first().map(|(index, value)| (*index, value))
+    }
+
+    /// Last entry, the current value and the index it was set at.
+    pub fn last(&self) -> Option<(u64, &T)> {
+        self.0.last().map(|(index, value)| (*index, value))
+    }
+
+    /// Number of changes.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
     pub fn is_empty(&self) -> bool {
         self.0.is_empty()
     }
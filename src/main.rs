@@ -1,201 +1,188 @@
+mod bench;
+mod block;
+mod change_set;
+mod class;
+mod config;
 mod contract;
 mod db;
+mod event;
+mod gateway;
 mod history;
+mod query;
 mod request;
+mod rest;
+mod rpc;
+mod server;
 mod state_update;
+mod sync;
+mod transaction;
 
-use db::Database;
-use state_update::{DeclaredClass, StateUpdate, StorageDiff};
-
-const FEEDER_GATEWAY: &str = "https://alpha-mainnet.starknet.io/feeder_gateway";
-//const FEEDER_GATEWAY: &str = "http://127.0.0.1:3000/feeder_gateway";
+use std::process::ExitCode;
+use std::sync::Arc;
 
-const START_BLOCK: u64 = 0;
-const END_BLOCK: u64 = 10_000;
+use clap::Parser;
+use config::{Cli, Command, Config};
+use db::Database;
+use sync::Syncer;
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn main() -> ExitCode {
     env_logger::init();
 
-    log::info!("🚀 Starting Starknet Explorer 🚀");
-    let db = std::sync::Arc::new(Database::new("db").unwrap());
-    log::info!("💾 Database created");
-
-    log::info!("🔄 Syncing from block {} to {}", START_BLOCK, END_BLOCK);
-    match sync(db.clone(), START_BLOCK, END_BLOCK).await {
-        Ok(_) => log::info!("🚀 Synced"),
-        Err(e) => log::error!("❌ Sync error: {e}"),
-    }
-
-    println!("🚀 Welcome to the Starknet CLI Explorer 🚀");
-    loop {
-        match request::prompt(&db) {
-            Ok(true) => break,
-            Ok(false) => continue,
-            Err(e) => println!("❌ Error: {e}"),
+    match run(Cli::parse()).await {
+        Ok(code) => ExitCode::from(code),
+        Err(e) => {
+            eprintln!("❌ Error: {e}");
+            ExitCode::from(query::EXIT_ERROR)
         }
     }
-    log::info!("Exiting");
-    drop(db);
-    Ok(())
 }
 
-const SYMULTANEOUS_REQUESTS: usize = 20;
-
-async fn sync(
-    db: std::sync::Arc<Database>,
-    start_block: u64,
-    end_block: u64,
-) -> Result<(), String> {
-    // Instantiate the client (could be reused for multiple requests)
-    let client = std::sync::Arc::new(reqwest::Client::new());
-
-    for block_number in (start_block..=end_block).step_by(SYMULTANEOUS_REQUESTS) {
-        log::info!(
-            "Processing block: {} to {}",
-            block_number,
-            block_number + SYMULTANEOUS_REQUESTS as u64
-        );
-        let block_number = block_number..block_number + SYMULTANEOUS_REQUESTS as u64;
-        let time = std::time::Instant::now();
-        let fetches = block_number
-            .map(|number| (fetch_and_deserialize(number, client.clone())))
-            .collect::<Vec<_>>();
-
-        let results = futures::future::join_all(fetches).await;
-        log::info!("Fetched and deserialized in {:?}", time.elapsed());
-        let time = std::time::Instant::now();
-
-        for result in results {
-            let (block_number, state_update) = result.map_err(|e| format!("fetch error: {e}"))?;
-            let mut handles_deployed_contract = Vec::new();
-            let mut handles_replaced_contract = Vec::new();
-            let mut handles_nonce = Vec::new();
-            let mut handles_key = Vec::new();
-            // insert new classes into the class tree
-            for DeclaredClass {
-                class_hash,
-                compiled_class_hash,
-            } in state_update.state_diff.declared_classes
-            {}
-
-            // insert new contracts into the contract tree
-            for deployed_contract in state_update.state_diff.deployed_contracts {
-                let db_clone = db.clone();
-                let handle = tokio::spawn(async move {
-                    let _ = db_clone
-                        .insert_class_hash(
-                            deployed_contract.address,
-                            deployed_contract.class_hash,
-                            block_number,
-                        )
-                        .map_err(|e| log::error!("insert class hash error: {e}"));
-                });
-                handles_deployed_contract.push(handle);
-            }
-
-            // update the contracts class hash in the contract tree
-            for replaced_contract in state_update.state_diff.replaced_classes {
-                let db_clone = db.clone();
-                let handle = tokio::spawn(async move {
-                    let _ = db_clone
-                        .insert_class_hash(
-                            replaced_contract.address,
-                            replaced_contract.class_hash,
-                            block_number,
-                        )
-                        .map_err(|e| log::error!("insert class hash error: {e}"));
-                });
-                handles_replaced_contract.push(handle);
-            }
-
-            // update nonces into the contract tree
-            for (contract_address, nonce) in state_update.state_diff.nonces {
-                let db_clone = db.clone();
-                let handle = tokio::spawn(async move {
-                    let _ = db_clone
-                        .insert_nonce(contract_address, nonce, block_number)
-                        .map_err(|e| log::error!("insert nonce error: {e}"));
-                });
-                handles_nonce.push(handle);
-            }
+async fn run(cli: Cli) -> Result<u8, Box<dyn std::error::Error>> {
+    let config = Arc::new(Config::load(&cli)?);
 
-            // insert key-value pairs into the contract tree
-            for (contract_address, storage_diffs) in state_update.state_diff.storage_diffs {
-                for StorageDiff { key, value } in storage_diffs {
-                    let db_clone = db.clone();
-                    let handle = tokio::spawn(async move {
-                        let _ = db_clone
-                            .insert_key(contract_address, key, value, block_number)
-                            .map_err(|e| log::error!("insert key error: {e}"));
-                    });
-                    handles_key.push(handle);
-                }
-            }
+    log::info!("🚀 Starting Starknet Explorer 🚀");
+    if let Some(Command::Bench(args)) = &cli.command {
+        bench::run(args)?;
+        return Ok(query::EXIT_FOUND);
+    }
 
-            tokio::join!(
-                futures::future::join_all(handles_deployed_contract),
-                futures::future::join_all(handles_replaced_contract),
-                futures::future::join_all(handles_nonce),
-                futures::future::join_all(handles_key)
+    if let Some(Command::Migrate) = &cli.command {
+        let from_version = Database::migrate(&config.db_path, config.network)?;
+        if from_version == db::SCHEMA_VERSION {
+            println!("Database already at schema version {}", from_version);
+        } else {
+            println!(
+                "✅ Database migrated from schema version {} to {}",
+                from_version,
+                db::SCHEMA_VERSION
             );
         }
-        log::info!("Processed blocks in {:?}", time.elapsed());
+        return Ok(query::EXIT_FOUND);
     }
-    Ok(())
-}
-
-const MAX_ATTEMPS: u32 = 20;
-
-async fn fetch_and_deserialize(
-    block_number: u64,
-    client: std::sync::Arc<reqwest::Client>,
-) -> Result<(u64, StateUpdate), String> {
-    let client = client.as_ref();
 
-    let url = format!(
-        "{}/get_state_update?blockNumber={}",
-        FEEDER_GATEWAY, block_number
+    // only the chain of the database can be synced into it, other commands
+    // open it whatever its network unless one is given, and never create it
+    let syncs = match &cli.command {
+        None | Some(Command::Sync(_) | Command::Follow(_)) => true,
+        Some(Command::Serve(args)) => args.follow,
+        _ => false,
+    };
+    let db = Arc::new(if syncs {
+        Database::new(
+            &config.db_path,
+            Some(config.network.unwrap_or_default()),
+            config.storage_layout.unwrap_or_default(),
+        )?
+    } else {
+        Database::open_existing(&config.db_path, config.network)?
+    });
+    log::info!(
+        "💾 Database opened at {} (schema version {}, {:?} storage layout)",
+        config.db_path,
+        db.metadata().schema_version,
+        db.metadata().storage_layout
     );
+    if config
+        .storage_layout
+        .is_some_and(|storage_layout| storage_layout != db.storage_layout())
+    {
+        log::warn!(
+            "The database was created with the {:?} storage layout, ignoring the requested layout",
+            db.storage_layout()
+        );
+    }
 
-    let mut attempts = 0;
-
-    let mut response;
-
-    while attempts <= MAX_ATTEMPS {
-        response = client
-            .get(&url)
-            .send()
-            .await
-            .map_err(|e| format!("send fail: {e}"))?;
-
-        let status = response.status();
-
-        match status {
-            reqwest::StatusCode::OK => {
-                // Deserialize the JSON into a Rust struct
-                let state_update = response
-                    .json::<StateUpdate>()
-                    .await
-                    .map_err(|e| format!("serialisation: {e}"))?;
+    if let Some(command) = cli
+        .command
+        .as_ref()
+        .filter(|command| query::is_query(command))
+    {
+        return Ok(query::run(&db, command));
+    }
 
-                return Ok((block_number, state_update));
-            }
-            reqwest::StatusCode::TOO_MANY_REQUESTS => {
-                log::info!("Too many requests, waiting...");
-                let wait_time = 2u64.pow(attempts) * 1000; // expo
-                tokio::time::sleep(tokio::time::Duration::from_millis(wait_time)).await;
-                attempts += 1;
-                continue;
+    let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+    let mut syncer = Syncer::new(db.clone(), config.clone(), shutdown_rx.clone());
+
+    let follower = match &cli.command {
+        Some(Command::Explore) => None,
+        // one-shot queries returned above
+        Some(
+            Command::Storage(_)
+            | Command::StorageDump(_)
+            | Command::Contracts(_)
+            | Command::ClassUsage(_)
+            | Command::Nonce(_)
+            | Command::ClassHash(_)
+            | Command::Revert(_)
+            | Command::Changes(_)
+            | Command::History(_),
+        ) => None,
+        // run above
+        Some(Command::Bench(_) | Command::Migrate) => None,
+        Some(Command::Serve(args)) if !args.follow => None,
+        Some(Command::Follow(_)) | Some(Command::Serve(_)) => {
+            log::info!(
+                "🔄 Following the chain tip using {}",
+                config.feeder_gateway()
+            );
+            Some(tokio::spawn(async move {
+                if let Err(e) = syncer.follow().await {
+                    log::error!("❌ Sync error: {e}");
+                }
+            }))
+        }
+        Some(Command::Sync(_)) | None => {
+            log::info!(
+                "🔄 Syncing from block {} to {} using {}",
+                config.sync.start_block,
+                config.sync.end_block,
+                config.feeder_gateway()
+            );
+            match syncer.sync().await {
+                Ok(_) => log::info!("🚀 Synced"),
+                Err(e) => log::error!("❌ Sync error: {e}"),
             }
-            _ => {
-                log::info!("code status: {status}, waiting...");
-                let wait_time = 2u64.pow(attempts) * 1000; // expo
-                tokio::time::sleep(tokio::time::Duration::from_millis(wait_time)).await;
-                attempts += 1;
-                continue;
-            } //_ => return Err(format!("code status: {status}")),
+            None
         }
+    };
+
+    if let Some(Command::Serve(_)) = &cli.command {
+        let mut server = tokio::spawn(server::serve(
+            db.clone(),
+            config.server.address.clone(),
+            shutdown_rx,
+        ));
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result?,
+            // failed to bind
+            result = &mut server => result??,
+        }
+        let _ = shutdown_tx.send(true);
+        if !server.is_finished() {
+            server.await??;
+        }
+    } else {
+        // the prompt is blocking, keep it off the runtime so the follower can progress
+        let prompt_db = db.clone();
+        tokio::task::spawn_blocking(move || {
+            println!("🚀 Welcome to the Starknet CLI Explorer 🚀");
+            loop {
+                match request::prompt(&prompt_db) {
+                    Ok(true) => break,
+                    Ok(false) => continue,
+                    Err(e) => println!("❌ Error: {e}"),
+                }
+            }
+        })
+        .await?;
     }
-    Err("max attempts".into())
+
+    log::info!("Exiting");
+    let _ = shutdown_tx.send(true);
+    if let Some(follower) = follower {
+        follower.await?;
+    }
+    drop(db);
+    Ok(query::EXIT_FOUND)
 }
@@ -0,0 +1,503 @@
+use std::io::{IsTerminal, Write};
+
+use dialoguer::Confirm;
+use serde_json::{json, Map, Value};
+use starknet_api::hash::StarkFelt;
+
+use crate::config::{
+    ChangesArgs, ClassUsageArgs, Command, ContractArgs, ContractsArgs, HistoryCommand,
+    HistoryRangeArgs, OutputFormat, QueryArgs, RevertArgs, StorageArgs, StorageDumpArgs,
+};
+use crate::contract::ContractFilter;
+use crate::db::Database;
+use crate::history::History;
+
+/// Exit code of a query that found its value.
+pub const EXIT_FOUND: u8 = 0;
+/// Exit code of a query whose value does not exist.
+pub const EXIT_NOT_FOUND: u8 = 1;
+/// Exit code of a failed command.
+pub const EXIT_ERROR: u8 = 2;
+
+/// Whether a command is a one-shot query, run without syncing or prompting.
+pub fn is_query(command: &Command) -> bool {
+    matches!(
+        command,
+        Command::Storage(_)
+            | Command::StorageDump(_)
+            | Command::Contracts(_)
+            | Command::ClassUsage(_)
+            | Command::Nonce(_)
+            | Command::ClassHash(_)
+            | Command::Revert(_)
+            | Command::Changes(_)
+            | Command::History(_)
+    )
+}
+
+/// Run a one-shot query, printing its result on stdout. Returns the exit code
+/// of the process.
+pub fn run(db: &Database, command: &Command) -> u8 {
+    let result = match command {
+        Command::Storage(args) => storage(db, args),
+        Command::StorageDump(args) => storage_dump(db, args),
+        Command::Contracts(args) => contracts(db, args),
+        Command::ClassUsage(args) => class_usage(db, args),
+        Command::Nonce(args) => nonce(db, args),
+        Command::ClassHash(args) => class_hash(db, args),
+        Command::Revert(args) => revert(db, args),
+        Command::Changes(args) => changes(db, args),
+        Command::History(command) => history(db, command),
+        _ => Err("not a query command".to_string()),
+    };
+    match result {
+        Ok(true) => EXIT_FOUND,
+        Ok(false) => EXIT_NOT_FOUND,
+        Err(e) => {
+            eprintln!("❌ Error: {e}");
+            EXIT_ERROR
+        }
+    }
+}
+
+fn storage(db: &Database, args: &StorageArgs) -> Result<bool, String> {
+    let Some(block_number) = resolve_block(db, &args.query)? else {
+        return Ok(false);
+    };
+    let value = db
+        .get_key_at(args.contract, args.key, block_number)
+        .map_err(|e| format!("Database error: {e}"))?;
+    print_record(
+        args.query.output.format,
+        &[
+            ("contract", json!(args.contract)),
+            ("key", json!(args.key)),
+            ("block_number", json!(block_number)),
+            ("value", json!(value)),
+        ],
+    );
+    Ok(value.is_some())
+}
+
+/// Stream the storage of a contract to stdout. When `--limit` stops the dump
+/// early, the key to resume from is given as `next_key`.
+fn storage_dump(db: &Database, args: &StorageDumpArgs) -> Result<bool, String> {
+    let Some(block_number) = resolve_block(db, &args.query)? else {
+        return Ok(false);
+    };
+    let slots = db
+        .storage_at(
+            args.contract,
+            block_number,
+            args.from_key.unwrap_or_default(),
+        )
+        .map_err(|e| format!("Database error: {e}"))?;
+    let format = args.query.output.format;
+    let limit = args.limit.unwrap_or(usize::MAX);
+
+    let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+    let write_error = |e: std::io::Error| format!("Cannot write output: {e}");
+    match format {
+        OutputFormat::Json => write!(
+            out,
+            "{{\"contract\":{},\"block_number\":{},\"storage\":[",
+            json!(args.contract),
+            block_number
+        ),
+        OutputFormat::Csv => writeln!(out, "key,value"),
+        OutputFormat::Text => Ok(()),
+    }
+    .map_err(write_error)?;
+
+    let mut count = 0;
+    let mut next_key = None;
+    for slot in slots {
+        let (key, value) = slot.map_err(|e| format!("Database error: {e}"))?;
+        if count == limit {
+            next_key = Some(key);
+            break;
+        }
+        match format {
+            OutputFormat::Json => write!(
+                out,
+                "{}\n{}",
+                if count == 0 { "" } else { "," },
+                json!({ "key": key, "value": value })
+            ),
+            OutputFormat::Csv => writeln!(out, "{},{}", key, value),
+            OutputFormat::Text => writeln!(out, "{} {}", key, value),
+        }
+        .map_err(write_error)?;
+        count += 1;
+    }
+
+    match format {
+        OutputFormat::Json => writeln!(out, "],\"next_key\":{}}}", json!(next_key)),
+        _ => Ok(()),
+    }
+    .map_err(write_error)?;
+    out.flush().map_err(write_error)?;
+    if format != OutputFormat::Json {
+        if let Some(next_key) = next_key {
+            eprintln!(
+                "Stopped after {} slots, resume with --from-key {}",
+                count, next_key
+            );
+        }
+    }
+    Ok(count > 0)
+}
+
+fn contracts(db: &Database, args: &ContractsArgs) -> Result<bool, String> {
+    let filter = ContractFilter {
+        deployed_from: args.deployed_from,
+        deployed_to: args.deployed_to,
+        class_hash: args.class_hash,
+    };
+    let page = db
+        .list_contracts(
+            &filter,
+            args.from_address.unwrap_or_default(),
+            args.limit.unwrap_or(usize::MAX),
+        )
+        .map_err(|e| format!("Database error: {e}"))?;
+
+    match args.output.format {
+        OutputFormat::Json => println!("{}", json!(page)),
+        format => {
+            let separator = if format == OutputFormat::Csv {
+                ","
+            } else {
+                " "
+            };
+            if format == OutputFormat::Csv {
+                println!("address,deployed_at,class_hash,nonce,class_replacements");
+            }
+            for contract in &page.contracts {
+                let fields = [
+                    json!(contract.address),
+                    json!(contract.deployed_at),
+                    json!(contract.class_hash),
+                    json!(contract.nonce),
+                    json!(contract.class_replacements),
+                ];
+                let fields: Vec<String> = fields.iter().map(text).collect();
+                println!("{}", fields.join(separator));
+            }
+            if let Some(next_address) = page.next_address {
+                eprintln!(
+                    "Stopped after {} contracts, resume with --from-address {}",
+                    page.contracts.len(),
+                    next_address
+                );
+            }
+        }
+    }
+    Ok(!page.contracts.is_empty())
+}
+
+fn class_usage(db: &Database, args: &ClassUsageArgs) -> Result<bool, String> {
+    let usages: Vec<_> = db
+        .get_class_usage(args.class_hash)
+        .map_err(|e| format!("Database error: {e}"))?
+        .into_iter()
+        .filter(|usage| !args.current || usage.to_block.is_none())
+        .collect();
+
+    match args.output.format {
+        OutputFormat::Json => println!(
+            "{}",
+            json!({ "class_hash": args.class_hash, "contracts": usages })
+        ),
+        format => {
+            let separator = if format == OutputFormat::Csv {
+                ","
+            } else {
+                " "
+            };
+            if format == OutputFormat::Csv {
+                println!("contract,from_block,to_block");
+            }
+            for usage in &usages {
+                let fields = [
+                    json!(usage.contract),
+                    json!(usage.from_block),
+                    json!(usage.to_block),
+                ];
+                let fields: Vec<String> = fields.iter().map(text).collect();
+                println!("{}", fields.join(separator));
+            }
+            if format == OutputFormat::Text && usages.is_empty() {
+                eprintln!("🤷‍♂️ No contract found");
+            }
+        }
+    }
+    Ok(!usages.is_empty())
+}
+
+fn nonce(db: &Database, args: &ContractArgs) -> Result<bool, String> {
+    let Some(block_number) = resolve_block(db, &args.query)? else {
+        return Ok(false);
+    };
+    let nonce = db
+        .get_nonce_at(args.contract, block_number)
+        .map_err(|e| format!("Database error: {e}"))?;
+    print_record(
+        args.query.output.format,
+        &[
+            ("contract", json!(args.contract)),
+            ("block_number", json!(block_number)),
+            ("nonce", json!(nonce)),
+        ],
+    );
+    Ok(nonce.is_some())
+}
+
+fn class_hash(db: &Database, args: &ContractArgs) -> Result<bool, String> {
+    let Some(block_number) = resolve_block(db, &args.query)? else {
+        return Ok(false);
+    };
+    let class_hash = db
+        .get_class_hash_at(args.contract, block_number)
+        .map_err(|e| format!("Database error: {e}"))?;
+    print_record(
+        args.query.output.format,
+        &[
+            ("contract", json!(args.contract)),
+            ("block_number", json!(block_number)),
+            ("class_hash", json!(class_hash)),
+        ],
+    );
+    Ok(class_hash.is_some())
+}
+
+fn revert(db: &Database, args: &RevertArgs) -> Result<bool, String> {
+    if !args.yes {
+        // never wait for an answer that cannot come
+        if !std::io::stdin().is_terminal() {
+            return Err("refusing to revert without --yes".to_string());
+        }
+        let confirmed = Confirm::new()
+            .with_prompt(format!("Revert the database to block {}?", args.to))
+            .default(false)
+            .interact()
+            .map_err(|_| "Invalid answer")?;
+        if !confirmed {
+            return Err("revert cancelled".to_string());
+        }
+    }
+
+    db.revert_to(args.to)
+        .map_err(|e| format!("Database error: {e}"))?;
+    print_record(args.output.format, &[("reverted_to", json!(args.to))]);
+    Ok(true)
+}
+
+/// Print the change set of a block: one `kind address key previous value`
+/// row per change as text or CSV, the change set itself as JSON.
+fn changes(db: &Database, args: &ChangesArgs) -> Result<bool, String> {
+    let Some(changes) = db
+        .get_change_set(args.block)
+        .map_err(|e| format!("Database error: {e}"))?
+    else {
+        eprintln!("🤷‍♂️ No change set for block {}", args.block);
+        return Ok(false);
+    };
+
+    let format = args.output.format;
+    if format == OutputFormat::Json {
+        println!(
+            "{}",
+            json!({ "block_number": args.block, "changes": changes })
+        );
+        return Ok(true);
+    }
+
+    let mut rows = Vec::new();
+    for change in &changes.storage {
+        rows.push([
+            json!("storage"),
+            json!(change.contract),
+            json!(change.key),
+            json!(change.previous),
+            json!(change.value),
+        ]);
+    }
+    for (kind, contract_changes) in [
+        ("nonce", &changes.nonces),
+        ("class_hash", &changes.class_hashes),
+    ] {
+        for change in contract_changes {
+            rows.push([
+                json!(kind),
+                json!(change.contract),
+                Value::Null,
+                json!(change.previous),
+                json!(change.value),
+            ]);
+        }
+    }
+    for class_hash in &changes.declared_classes {
+        rows.push([
+            json!("declared_class"),
+            json!(class_hash),
+            Value::Null,
+            Value::Null,
+            Value::Null,
+        ]);
+    }
+    for change in &changes.compiled_class_hashes {
+        rows.push([
+            json!("compiled_class_hash"),
+            json!(change.class_hash),
+            Value::Null,
+            json!(change.previous),
+            json!(change.value),
+        ]);
+    }
+
+    let separator = if format == OutputFormat::Csv {
+        ","
+    } else {
+        " "
+    };
+    if format == OutputFormat::Csv {
+        println!("kind,address,key,previous,value");
+    }
+    for row in &rows {
+        let fields: Vec<String> = row.iter().map(text).collect();
+        println!("{}", fields.join(separator));
+    }
+    if format == OutputFormat::Text && changes.is_empty() {
+        eprintln!("🤷‍♂️ No change in block {}", args.block);
+    }
+    Ok(true)
+}
+
+fn history(db: &Database, command: &HistoryCommand) -> Result<bool, String> {
+    let (fields, history, range) = match command {
+        HistoryCommand::Storage {
+            contract,
+            key,
+            range,
+        } => (
+            vec![("contract", json!(contract)), ("key", json!(key))],
+            db.get_key_history(*contract, *key),
+            range,
+        ),
+        HistoryCommand::Nonce { contract, range } => (
+            vec![("contract", json!(contract))],
+            db.get_nonce_history(*contract),
+            range,
+        ),
+        HistoryCommand::ClassHash { contract, range } => (
+            vec![("contract", json!(contract))],
+            db.get_class_hash_history(*contract),
+            range,
+        ),
+    };
+    let history = history.map_err(|e| format!("Database error: {e}"))?;
+    print_history(&fields, &history, range);
+    Ok(!history.is_empty())
+}
+
+/// Print the changes of a history in the requested range: one `block value`
+/// line per change as text or CSV, an object with the history summary as JSON.
+fn print_history(fields: &[(&str, Value)], history: &History<StarkFelt>, range: &HistoryRangeArgs) {
+    let changes = history.range(range.from.unwrap_or(0), range.to.unwrap_or(u64::MAX));
+    match range.output.format {
+        OutputFormat::Json => {
+            let mut object: Map<String, Value> = fields
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.clone()))
+                .collect();
+            object.insert("total_changes".into(), json!(history.len()));
+            object.insert(
+                "first_change".into(),
+                json!(history.first().map(|(block_number, _)| block_number)),
+            );
+            object.insert(
+                "last_change".into(),
+                json!(history.last().map(|(block_number, _)| block_number)),
+            );
+            let changes: Vec<Value> = changes
+                .map(
+                    |(block_number, value)| json!({ "block_number": block_number, "value": value }),
+                )
+                .collect();
+            object.insert("changes".into(), Value::Array(changes));
+            println!("{}", Value::Object(object));
+        }
+        OutputFormat::Csv => {
+            println!("block_number,value");
+            for (block_number, value) in changes {
+                println!("{},{}", block_number, value);
+            }
+        }
+        OutputFormat::Text => {
+            if history.is_empty() {
+                eprintln!("🤷‍♂️ No change found");
+            }
+            for (block_number, value) in changes {
+                println!("{} {}", block_number, value);
+            }
+        }
+    }
+}
+
+/// Block to read the state at: the requested block, or the latest synced one.
+/// `None`, with a message on stderr, if that block is not synced.
+fn resolve_block(db: &Database, args: &QueryArgs) -> Result<Option<u64>, String> {
+    let cursor = db
+        .get_sync_cursor()
+        .map_err(|e| format!("Database error: {e}"))?;
+    let Some(cursor) = cursor else {
+        eprintln!("🤷‍♂️ The database is empty");
+        return Ok(None);
+    };
+    match args.block {
+        Some(block_number) if block_number > cursor.block_number => {
+            eprintln!(
+                "🤷‍♂️ Block {} is not synced, the last synced block is {}",
+                block_number, cursor.block_number
+            );
+            Ok(None)
+        }
+        Some(block_number) => Ok(Some(block_number)),
+        None => Ok(Some(cursor.block_number)),
+    }
+}
+
+/// Print a record: as a JSON object, as a CSV header and row, or as text
+/// where only the last field, the answer of the query, is printed.
+fn print_record(format: OutputFormat, fields: &[(&str, Value)]) {
+    match format {
+        OutputFormat::Json => {
+            let object: Map<String, Value> = fields
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.clone()))
+                .collect();
+            println!("{}", Value::Object(object));
+        }
+        OutputFormat::Csv => {
+            let names: Vec<&str> = fields.iter().map(|(name, _)| *name).collect();
+            let values: Vec<String> = fields.iter().map(|(_, value)| text(value)).collect();
+            println!("{}", names.join(","));
+            println!("{}", values.join(","));
+        }
+        OutputFormat::Text => match fields.last() {
+            Some((name, Value::Null)) => eprintln!("🤷‍♂️ {} not found", name),
+            Some((_, value)) => println!("{}", text(value)),
+            None => {}
+        },
+    }
+}
+
+/// Value without JSON quoting, empty for `null`.
+fn text(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(value) => value.clone(),
+        value => value.to_string(),
+    }
+}
@@ -1,12 +1,14 @@
-use dialoguer::{Input, Select};
+use dialoguer::{Confirm, Input, Select};
 use starknet_api::hash::StarkFelt;
 
 use crate::db::Database;
+use crate::event::EventFilter;
 
 #[derive(Debug, Default)]
 struct Request {
     request_type: String,
     contract: Option<StarkFelt>,
+    class_hash: Option<StarkFelt>,
     key: Option<StarkFelt>,
     block: Option<u64>,
 }
@@ -16,6 +18,15 @@ pub fn prompt(db: &Database) -> Result<bool, String> {
         "class_hash",
         "nonce",
         "storage_key",
+        "history",
+        "class",
+        "block",
+        "block_transactions",
+        "transaction",
+        "account_transactions",
+        "events",
+        "check_chain",
+        "reorgs",
         "revert",
         "flush_db",
         "quit",
@@ -116,6 +127,402 @@ pub fn prompt(db: &Database) -> Result<bool, String> {
             }
         }
 
+        "history" => {
+            let kinds = ["storage_key", "nonce", "class_hash"];
+            let kind = Select::new()
+                .with_prompt("Select history")
+                .default(0)
+                .items(&kinds[..])
+                .interact()
+                .map_err(|_| "Invalid selection")?;
+
+            let contract = Input::<String>::new()
+                .with_prompt("Enter contract address")
+                .interact_text()
+                .map_err(|_| "Invalid contract address")?;
+            request.contract = Some(
+                StarkFelt::try_from(contract.as_str()).map_err(|_| "Invalid contract address")?,
+            );
+
+            if kinds[kind] == "storage_key" {
+                let key = Input::<String>::new()
+                    .with_prompt("Enter key")
+                    .interact_text()
+                    .map_err(|_| "Invalid key")?;
+                request.key = Some(StarkFelt::try_from(key.as_str()).map_err(|_| "Invalid key")?);
+            }
+
+            let time = std::time::Instant::now();
+            let history = match kinds[kind] {
+                "storage_key" => {
+                    db.get_key_history(request.contract.unwrap(), request.key.unwrap())
+                }
+                "nonce" => db.get_nonce_history(request.contract.unwrap()),
+                _ => db.get_class_hash_history(request.contract.unwrap()),
+            }
+            .map_err(|e| format!("Database error: {e}"))?;
+            log::info!("⏳ Processed request in {:?}", time.elapsed());
+            match (history.first(), history.last()) {
+                (Some((first, _)), Some((last, _))) => println!(
+                    "{} changes, first at block {}, last at block {}",
+                    history.len(),
+                    first,
+                    last
+                ),
+                _ => println!("🤷‍♂️ No change found"),
+            }
+            for (block_number, value) in history.iter() {
+                println!("Block {}: {}", block_number, value);
+            }
+        }
+
+        "class" => {
+            let class_hash = Input::<String>::new()
+                .with_prompt("Enter class hash")
+                .interact_text()
+                .map_err(|_| "Invalid class hash")?;
+            request.class_hash =
+                Some(StarkFelt::try_from(class_hash.as_str()).map_err(|_| "Invalid class hash")?);
+
+            let index = Input::<u64>::new()
+                .with_prompt("Enter block number")
+                .interact_text()
+                .map_err(|_| "Invalid block number")?;
+            request.block = Some(index);
+
+            let time = std::time::Instant::now();
+            let declaration = db
+                .get_class_declaration(request.class_hash.unwrap())
+                .map_err(|e| format!("Database error: {e}"))?;
+            let compiled_class_hash = db
+                .get_compiled_class_hash_at(request.class_hash.unwrap(), request.block.unwrap())
+                .map_err(|e| format!("Database error: {e}"))?;
+            log::info!("⏳ Processed request in {:?}", time.elapsed());
+            match declaration {
+                Some(declaration) => {
+                    println!(
+                        "Declared at block {} ({:?})",
+                        declaration.block_number, declaration.cairo_version
+                    );
+                    match compiled_class_hash {
+                        Some(compiled_class_hash) => {
+                            println!("Compiled class hash: {}", compiled_class_hash)
+                        }
+                        None => println!("🤷‍♂️ No compiled class hash at this block"),
+                    }
+                }
+                None => println!("🤷‍♂️ Class not found"),
+            }
+        }
+
+        "block" => {
+            let block_id = Input::<String>::new()
+                .with_prompt("Enter block number or hash")
+                .interact_text()
+                .map_err(|_| "Invalid block")?;
+
+            let time = std::time::Instant::now();
+            let header = if block_id.starts_with("0x") {
+                let block_hash =
+                    StarkFelt::try_from(block_id.as_str()).map_err(|_| "Invalid block hash")?;
+                db.get_block_header_by_hash(block_hash)
+            } else {
+                let block_number = block_id
+                    .parse::<u64>()
+                    .map_err(|_| "Invalid block number")?;
+                db.get_block_header(block_number)
+            }
+            .map_err(|e| format!("Database error: {e}"))?;
+            log::info!("⏳ Processed request in {:?}", time.elapsed());
+            match header {
+                Some(header) => {
+                    println!("Block number: {}", header.block_number);
+                    println!("Block hash: {}", header.block_hash);
+                    println!("Old root: {}", header.old_root);
+                    println!("New root: {}", header.new_root);
+                    println!(
+                        "Storage diffs: {}, deployed contracts: {}, nonce updates: {}, declared classes: {}, replaced classes: {}",
+                        header.storage_diffs,
+                        header.deployed_contracts,
+                        header.nonce_updates,
+                        header.declared_classes,
+                        header.replaced_classes
+                    );
+                }
+                None => println!("🤷‍♂️ Block not found"),
+            }
+        }
+
+        "block_transactions" => {
+            let index = Input::<u64>::new()
+                .with_prompt("Enter block number")
+                .interact_text()
+                .map_err(|_| "Invalid block number")?;
+            request.block = Some(index);
+
+            let time = std::time::Instant::now();
+            let transactions = db
+                .get_block_transactions(request.block.unwrap())
+                .map_err(|e| format!("Database error: {e}"))?;
+            let receipts = db
+                .get_block_receipts(request.block.unwrap())
+                .map_err(|e| format!("Database error: {e}"))?;
+            log::info!("⏳ Processed request in {:?}", time.elapsed());
+            if transactions.is_empty() {
+                println!("🤷‍♂️ No transaction found");
+            }
+            for (index, (transaction, receipt)) in transactions.iter().zip(&receipts).enumerate() {
+                println!(
+                    "#{} {} {:?} status: {}, fee: {}, events: {}",
+                    index,
+                    transaction.transaction_hash,
+                    transaction.r#type,
+                    receipt.execution_status.as_deref().unwrap_or("SUCCEEDED"),
+                    receipt.actual_fee.unwrap_or_default(),
+                    receipt.events.len()
+                );
+            }
+        }
+
+        "transaction" => {
+            let hash = Input::<String>::new()
+                .with_prompt("Enter transaction hash")
+                .interact_text()
+                .map_err(|_| "Invalid transaction hash")?;
+            let hash =
+                StarkFelt::try_from(hash.as_str()).map_err(|_| "Invalid transaction hash")?;
+
+            let time = std::time::Instant::now();
+            let location = db
+                .get_transaction_location(hash)
+                .map_err(|e| format!("Database error: {e}"))?;
+            let Some(location) = location else {
+                log::info!("⏳ Processed request in {:?}", time.elapsed());
+                println!("🤷‍♂️ Transaction not found");
+                return Ok(false);
+            };
+            let transaction = db
+                .get_transaction(location.block_number, location.index)
+                .map_err(|e| format!("Database error: {e}"))?
+                .ok_or("Transaction not found")?;
+            let receipt = db
+                .get_receipt(location.block_number, location.index)
+                .map_err(|e| format!("Database error: {e}"))?
+                .ok_or("Receipt not found")?;
+            log::info!("⏳ Processed request in {:?}", time.elapsed());
+
+            println!(
+                "Block {}, index {}: {:?}",
+                location.block_number, location.index, transaction.r#type
+            );
+            if let Some((account, nonce)) = transaction.account_nonce() {
+                println!("Sender: {}, nonce: {}", account, nonce);
+            }
+            let calldata = if transaction.calldata.is_empty() {
+                &transaction.constructor_calldata
+            } else {
+                &transaction.calldata
+            };
+            println!(
+                "Calldata: {:?}",
+                calldata
+                    .iter()
+                    .map(|value| value.to_string())
+                    .collect::<Vec<_>>()
+            );
+            println!(
+                "Status: {}, fee: {}",
+                receipt.execution_status.as_deref().unwrap_or("SUCCEEDED"),
+                receipt.actual_fee.unwrap_or_default()
+            );
+            if let Some(revert_error) = &receipt.revert_error {
+                println!("Revert error: {}", revert_error);
+            }
+            for event in &receipt.events {
+                println!(
+                    "Event from {}: keys {:?}, data {:?}",
+                    event.from_address,
+                    event
+                        .keys
+                        .iter()
+                        .map(|key| key.to_string())
+                        .collect::<Vec<_>>(),
+                    event
+                        .data
+                        .iter()
+                        .map(|value| value.to_string())
+                        .collect::<Vec<_>>()
+                );
+            }
+        }
+
+        "account_transactions" => {
+            let contract = Input::<String>::new()
+                .with_prompt("Enter account address")
+                .interact_text()
+                .map_err(|_| "Invalid account address")?;
+            request.contract = Some(
+                StarkFelt::try_from(contract.as_str()).map_err(|_| "Invalid account address")?,
+            );
+
+            let time = std::time::Instant::now();
+            let transactions = db
+                .get_account_transactions(request.contract.unwrap(), StarkFelt::default(), 100)
+                .map_err(|e| format!("Database error: {e}"))?;
+            let mismatches = db
+                .check_account_nonces(request.contract.unwrap())
+                .map_err(|e| format!("Database error: {e}"))?;
+            log::info!("⏳ Processed request in {:?}", time.elapsed());
+            if transactions.is_empty() {
+                println!("🤷‍♂️ No transaction found");
+            }
+            for (nonce, location) in &transactions {
+                println!(
+                    "Nonce {}: block {}, index {}",
+                    nonce, location.block_number, location.index
+                );
+            }
+            if transactions.len() == 100 {
+                println!("... (first 100 transactions)");
+            }
+            if mismatches.is_empty() {
+                println!("✅ Transactions consistent with the nonce history");
+            }
+            for mismatch in &mismatches {
+                println!(
+                    "❌ Nonce {} in block {}: account nonce {:?} before the block, {:?} after",
+                    mismatch.nonce,
+                    mismatch.location.block_number,
+                    mismatch.nonce_before,
+                    mismatch.nonce_after
+                );
+            }
+        }
+
+        "events" => {
+            let contract = Input::<String>::new()
+                .with_prompt("Enter contract address (empty for any)")
+                .allow_empty(true)
+                .interact_text()
+                .map_err(|_| "Invalid contract address")?;
+            if !contract.is_empty() {
+                request.contract = Some(
+                    StarkFelt::try_from(contract.as_str())
+                        .map_err(|_| "Invalid contract address")?,
+                );
+            }
+
+            let key = Input::<String>::new()
+                .with_prompt("Enter first key (empty for any)")
+                .allow_empty(true)
+                .interact_text()
+                .map_err(|_| "Invalid key")?;
+            if !key.is_empty() {
+                request.key = Some(StarkFelt::try_from(key.as_str()).map_err(|_| "Invalid key")?);
+            }
+
+            let from = Input::<u64>::new()
+                .with_prompt("Enter first block")
+                .default(0)
+                .interact_text()
+                .map_err(|_| "Invalid block number")?;
+            let to = Input::<u64>::new()
+                .with_prompt("Enter last block")
+                .interact_text()
+                .map_err(|_| "Invalid block number")?;
+
+            let filter = EventFilter {
+                from_block: from,
+                to_block: Some(to),
+                address: request.contract,
+                keys: request.key.map(|key| vec![vec![key]]).unwrap_or_default(),
+            };
+            let mut continuation = None;
+            loop {
+                let time = std::time::Instant::now();
+                let page = db
+                    .get_events(&filter, continuation, 20)
+                    .map_err(|e| format!("Database error: {e}"))?;
+                log::info!("⏳ Processed request in {:?}", time.elapsed());
+                if page.events.is_empty() {
+                    println!("🤷‍♂️ No event found");
+                }
+                for event in &page.events {
+                    println!(
+                        "Block {} tx {} from {}: keys {:?}, data {:?}",
+                        event.block_number,
+                        event.transaction_hash,
+                        event.from_address,
+                        event
+                            .keys
+                            .iter()
+                            .map(|key| key.to_string())
+                            .collect::<Vec<_>>(),
+                        event
+                            .data
+                            .iter()
+                            .map(|value| value.to_string())
+                            .collect::<Vec<_>>()
+                    );
+                }
+                let Some(token) = page.continuation_token else {
+                    break;
+                };
+                let more = Confirm::new()
+                    .with_prompt("Show more events?")
+                    .default(true)
+                    .interact()
+                    .map_err(|_| "Invalid answer")?;
+                if !more {
+                    break;
+                }
+                continuation = Some(token.parse()?);
+            }
+        }
+
+        "check_chain" => {
+            let from = Input::<u64>::new()
+                .with_prompt("Enter first block number")
+                .interact_text()
+                .map_err(|_| "Invalid block number")?;
+            let to = Input::<u64>::new()
+                .with_prompt("Enter last block number")
+                .interact_text()
+                .map_err(|_| "Invalid block number")?;
+
+            let time = std::time::Instant::now();
+            let broken = db
+                .check_chain_continuity(from, to)
+                .map_err(|e| format!("Database error: {e}"))?;
+            log::info!("⏳ Processed request in {:?}", time.elapsed());
+            if broken.is_empty() {
+                println!("✅ Blocks {} to {} form a continuous chain", from, to);
+            } else {
+                println!("❌ Chain broken or missing at blocks: {:?}", broken);
+            }
+        }
+
+        "reorgs" => {
+            let reorgs = db
+                .get_reorgs()
+                .map_err(|e| format!("Database error: {e}"))?;
+            if reorgs.is_empty() {
+                println!("🤷‍♂️ No reorg recorded");
+            }
+            for reorg in reorgs {
+                println!(
+                    "🔀 Reorg of depth {} detected at block {} (timestamp {}), reverted to block {}",
+                    reorg.depth(),
+                    reorg.detected_at,
+                    reorg.timestamp,
+                    reorg.common_ancestor
+                );
+                for (block_number, block_hash) in reorg.orphaned {
+                    println!("    orphaned block {}: {}", block_number, block_hash);
+                }
+            }
+        }
+
         "revert" => {
             let index = Input::<u64>::new()
                 .with_prompt("Enter block number")
@@ -136,7 +543,7 @@ pub fn prompt(db: &Database) -> Result<bool, String> {
 
             let time = std::time::Instant::now();
             db.revert_to(request.block.unwrap())
-                .map_err(|e| format!("Database error: {:?}", e))?;
+                .map_err(|e| format!("Database error: {e}"))?;
             log::info!("⏳ Processed request in {:?}", time.elapsed());
             println!("🔙 Reverted to block {}", request.block.unwrap());
         }
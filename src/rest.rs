@@ -0,0 +1,206 @@
+use std::sync::Arc;
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use starknet_api::hash::StarkFelt;
+
+use crate::block::BlockHeader;
+use crate::db::{Database, DatabaseError};
+
+#[derive(thiserror::Error, Debug)]
+pub enum ApiError {
+    #[error("Invalid {0}")]
+    InvalidParameter(&'static str),
+    #[error("{0} not found")]
+    NotFound(&'static str),
+    #[error("Database error: {0}")]
+    Database(#[from] DatabaseError),
+    #[error("Internal error: {0}")]
+    Internal(String),
+}
+
+#[derive(Deserialize)]
+struct BlockQuery {
+    /// Block to read the state at, the latest synced block by default.
+    block: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct RangeQuery {
+    /// First block of the range, inclusive.
+    from: Option<u64>,
+    /// Last block of the range, inclusive.
+    to: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct ContractResponse {
+    address: StarkFelt,
+    block_number: u64,
+    class_hash: StarkFelt,
+    nonce: StarkFelt,
+}
+
+#[derive(Serialize)]
+struct StorageResponse {
+    address: StarkFelt,
+    key: StarkFelt,
+    block_number: u64,
+    value: StarkFelt,
+}
+
+#[derive(Serialize)]
+struct StorageHistoryResponse {
+    address: StarkFelt,
+    key: StarkFelt,
+    /// Number of changes of the slot, in and out of the requested range.
+    total_changes: usize,
+    changes: Vec<Change>,
+}
+
+#[derive(Serialize)]
+struct Change {
+    block_number: u64,
+    value: StarkFelt,
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            ApiError::InvalidParameter(_) => StatusCode::BAD_REQUEST,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Database(_) | ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status, Json(json!({ "error": self.to_string() }))).into_response()
+    }
+}
+
+/// Routes of the REST API.
+pub fn router() -> Router<Arc<Database>> {
+    Router::new()
+        .route("/contracts/:address", get(contract))
+        .route("/contracts/:address/storage/:key", get(storage))
+        .route(
+            "/contracts/:address/storage/:key/history",
+            get(storage_history),
+        )
+        .route("/blocks/:block_number", get(block))
+}
+
+async fn contract(
+    State(db): State<Arc<Database>>,
+    Path(address): Path<String>,
+    Query(query): Query<BlockQuery>,
+) -> Result<Json<ContractResponse>, ApiError> {
+    let address = parse_felt(&address, "contract address")?;
+    blocking(db, move |db| {
+        let block_number = resolve_block(db, query.block)?;
+        let class_hash = db
+            .get_class_hash_at(address, block_number)?
+            .ok_or(ApiError::NotFound("contract"))?;
+        let nonce = db.get_nonce_at(address, block_number)?;
+        Ok(Json(ContractResponse {
+            address,
+            block_number,
+            class_hash,
+            nonce: nonce.unwrap_or_default(),
+        }))
+    })
+    .await
+}
+
+async fn storage(
+    State(db): State<Arc<Database>>,
+    Path((address, key)): Path<(String, String)>,
+    Query(query): Query<BlockQuery>,
+) -> Result<Json<StorageResponse>, ApiError> {
+    let address = parse_felt(&address, "contract address")?;
+    let key = parse_felt(&key, "storage key")?;
+    blocking(db, move |db| {
+        let block_number = resolve_block(db, query.block)?;
+        let value = db.get_key_at(address, key, block_number)?;
+        Ok(Json(StorageResponse {
+            address,
+            key,
+            block_number,
+            // unset slots read as zero
+            value: value.unwrap_or_default(),
+        }))
+    })
+    .await
+}
+
+async fn storage_history(
+    State(db): State<Arc<Database>>,
+    Path((address, key)): Path<(String, String)>,
+    Query(query): Query<RangeQuery>,
+) -> Result<Json<StorageHistoryResponse>, ApiError> {
+    let address = parse_felt(&address, "contract address")?;
+    let key = parse_felt(&key, "storage key")?;
+    blocking(db, move |db| {
+        let history = db.get_key_history(address, key)?;
+        if history.is_empty() {
+            return Err(ApiError::NotFound("storage key"));
+        }
+        let changes = history
+            .range(query.from.unwrap_or(0), query.to.unwrap_or(u64::MAX))
+            .map(|(block_number, value)| Change {
+                block_number,
+                value: *value,
+            })
+            .collect();
+        Ok(Json(StorageHistoryResponse {
+            address,
+            key,
+            total_changes: history.len(),
+            changes,
+        }))
+    })
+    .await
+}
+
+async fn block(
+    State(db): State<Arc<Database>>,
+    Path(block_number): Path<u64>,
+) -> Result<Json<BlockHeader>, ApiError> {
+    blocking(db, move |db| {
+        db.get_block_header(block_number)?
+            .map(Json)
+            .ok_or(ApiError::NotFound("block"))
+    })
+    .await
+}
+
+/// Run a database query off the async runtime.
+async fn blocking<T, F>(db: Arc<Database>, query: F) -> Result<T, ApiError>
+where
+    T: Send + 'static,
+    F: FnOnce(&Database) -> Result<T, ApiError> + Send + 'static,
+{
+    tokio::task::spawn_blocking(move || query(&db))
+        .await
+        .map_err(|e| ApiError::Internal(e.to_string()))?
+}
+
+fn parse_felt(value: &str, name: &'static str) -> Result<StarkFelt, ApiError> {
+    StarkFelt::try_from(value).map_err(|_| ApiError::InvalidParameter(name))
+}
+
+/// Block to read the state at: the requested one, not found if it is not
+/// synced yet, or the latest when `block` is omitted.
+fn resolve_block(db: &Database, block: Option<u64>) -> Result<u64, ApiError> {
+    let latest = db
+        .get_sync_cursor()?
+        .ok_or(ApiError::NotFound("block"))?
+        .block_number;
+    match block {
+        Some(block_number) if block_number > latest => Err(ApiError::NotFound("block")),
+        Some(block_number) => Ok(block_number),
+        None => Ok(latest),
+    }
+}
@@ -0,0 +1,319 @@
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::Json;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use starknet_api::hash::StarkFelt;
+
+use crate::db::{Database, DatabaseError};
+
+/// Error of a JSON-RPC call, with the codes of the Starknet JSON-RPC spec.
+#[derive(thiserror::Error, Debug)]
+pub enum RpcError {
+    #[error("Parse error")]
+    ParseError,
+    #[error("Invalid request")]
+    InvalidRequest,
+    #[error("Method not found")]
+    MethodNotFound,
+    #[error("Invalid params: {0}")]
+    InvalidParams(String),
+    #[error("Contract not found")]
+    ContractNotFound,
+    #[error("Block not found")]
+    BlockNotFound,
+    #[error("There are no blocks")]
+    NoBlocks,
+    #[error("Internal error: {0}")]
+    Internal(String),
+}
+
+/// Block given as `block_id` parameter.
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(untagged)]
+pub enum BlockId {
+    Tag(BlockTag),
+    Number { block_number: u64 },
+    Hash { block_hash: StarkFelt },
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum BlockTag {
+    Latest,
+    /// Pending blocks are not synced, served as the latest block.
+    Pending,
+}
+
+#[derive(Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Deserialize)]
+struct StorageAtParams {
+    contract_address: StarkFelt,
+    key: StarkFelt,
+    block_id: BlockId,
+}
+
+#[derive(Deserialize)]
+struct ContractParams {
+    block_id: BlockId,
+    contract_address: StarkFelt,
+}
+
+#[derive(Deserialize)]
+struct BlockParams {
+    block_id: BlockId,
+}
+
+impl RpcError {
+    pub fn code(&self) -> i64 {
+        match self {
+            RpcError::ParseError => -32700,
+            RpcError::InvalidRequest => -32600,
+            RpcError::MethodNotFound => -32601,
+            RpcError::InvalidParams(_) => -32602,
+            RpcError::ContractNotFound => 20,
+            RpcError::BlockNotFound => 24,
+            RpcError::NoBlocks => 32,
+            RpcError::Internal(_) => -32603,
+        }
+    }
+}
+
+impl From<DatabaseError> for RpcError {
+    fn from(e: DatabaseError) -> Self {
+        RpcError::Internal(e.to_string())
+    }
+}
+
+/// Handle a JSON-RPC request or batch of requests.
+pub async fn handle(State(db): State<Arc<Database>>, body: String) -> Json<Value> {
+    // database reads are blocking
+    let response = tokio::task::spawn_blocking(move || match serde_json::from_str(&body) {
+        Ok(Value::Array(requests)) if !requests.is_empty() => Value::Array(
+            requests
+                .into_iter()
+                .map(|request| call(&db, request))
+                .collect(),
+        ),
+        Ok(request) => call(&db, request),
+        Err(_) => error_response(Value::Null, RpcError::ParseError),
+    })
+    .await;
+
+    Json(
+        response.unwrap_or_else(|e| error_response(Value::Null, RpcError::Internal(e.to_string()))),
+    )
+}
+
+fn call(db: &Database, request: Value) -> Value {
+    let request: Request = match serde_json::from_value(request) {
+        Ok(request) => request,
+        Err(_) => return error_response(Value::Null, RpcError::InvalidRequest),
+    };
+    match dispatch(db, &request.method, request.params) {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": request.id, "result": result }),
+        Err(e) => error_response(request.id, e),
+    }
+}
+
+fn error_response(id: Value, error: RpcError) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": error.code(), "message": error.to_string() },
+    })
+}
+
+fn dispatch(db: &Database, method: &str, params: Value) -> Result<Value, RpcError> {
+    match method {
+        "starknet_blockNumber" => Ok(json!(latest_block(db)?)),
+        "starknet_getStorageAt" => {
+            let params: StorageAtParams =
+                parse_params(params, &["contract_address", "key", "block_id"])?;
+            let block_number = resolve_block(db, params.block_id)?;
+            contract_class_hash(db, params.contract_address, block_number)?;
+            let value = db.get_key_at(params.contract_address, params.key, block_number)?;
+            Ok(json!(value.unwrap_or_default()))
+        }
+        "starknet_getNonce" => {
+            let params: ContractParams = parse_params(params, &["block_id", "contract_address"])?;
+            let block_number = resolve_block(db, params.block_id)?;
+            contract_class_hash(db, params.contract_address, block_number)?;
+            let nonce = db.get_nonce_at(params.contract_address, block_number)?;
+            Ok(json!(nonce.unwrap_or_default()))
+        }
+        "starknet_getClassHashAt" => {
+            let params: ContractParams = parse_params(params, &["block_id", "contract_address"])?;
+            let block_number = resolve_block(db, params.block_id)?;
+            Ok(json!(contract_class_hash(
+                db,
+                params.contract_address,
+                block_number
+            )?))
+        }
+        "starknet_getStateUpdate" => {
+            let params: BlockParams = parse_params(params, &["block_id"])?;
+            let block_number = resolve_block(db, params.block_id)?;
+            state_update(db, block_number)
+        }
+        "starknet_getBlockWithTxHashes" => {
+            let params: BlockParams = parse_params(params, &["block_id"])?;
+            let block_number = resolve_block(db, params.block_id)?;
+            block_with_tx_hashes(db, block_number)
+        }
+        _ => Err(RpcError::MethodNotFound),
+    }
+}
+
+/// Deserialize parameters given either by name or by position, in the order of `names`.
+fn parse_params<T: DeserializeOwned>(params: Value, names: &[&str]) -> Result<T, RpcError> {
+    let params = match params {
+        Value::Array(values) => {
+            if values.len() > names.len() {
+                return Err(RpcError::InvalidParams("too many parameters".into()));
+            }
+            Value::Object(
+                names
+                    .iter()
+                    .map(|name| name.to_string())
+                    .zip(values)
+                    .collect(),
+            )
+        }
+        params => params,
+    };
+    serde_json::from_value(params).map_err(|e| RpcError::InvalidParams(e.to_string()))
+}
+
+fn latest_block(db: &Database) -> Result<u64, RpcError> {
+    match db.get_sync_cursor()? {
+        Some(cursor) => Ok(cursor.block_number),
+        None => Err(RpcError::NoBlocks),
+    }
+}
+
+/// Number of a block stored in the database.
+fn resolve_block(db: &Database, block_id: BlockId) -> Result<u64, RpcError> {
+    match block_id {
+        BlockId::Tag(BlockTag::Latest | BlockTag::Pending) => match latest_block(db) {
+            Err(RpcError::NoBlocks) => Err(RpcError::BlockNotFound),
+            result => result,
+        },
+        BlockId::Number { block_number } => match db.get_block_header(block_number)? {
+            Some(_) => Ok(block_number),
+            None => Err(RpcError::BlockNotFound),
+        },
+        BlockId::Hash { block_hash } => db
+            .get_block_number(block_hash)?
+            .ok_or(RpcError::BlockNotFound),
+    }
+}
+
+/// Class hash of a contract, failing if it is not deployed at the given block.
+fn contract_class_hash(
+    db: &Database,
+    contract: StarkFelt,
+    block_number: u64,
+) -> Result<StarkFelt, RpcError> {
+    db.get_class_hash_at(contract, block_number)?
+        .ok_or(RpcError::ContractNotFound)
+}
+
+fn state_update(db: &Database, block_number: u64) -> Result<Value, RpcError> {
+    let header = db
+        .get_block_header(block_number)?
+        .ok_or(RpcError::BlockNotFound)?;
+    let state_diff = db
+        .get_state_diff(block_number)?
+        .ok_or(RpcError::BlockNotFound)?;
+
+    let storage_diffs: Vec<Value> = state_diff
+        .storage_diffs
+        .iter()
+        .map(|(address, diffs)| {
+            let storage_entries: Vec<Value> = diffs
+                .iter()
+                .map(|diff| json!({ "key": diff.key, "value": diff.value }))
+                .collect();
+            json!({ "address": address, "storage_entries": storage_entries })
+        })
+        .collect();
+    let declared_classes: Vec<Value> = state_diff
+        .declared_classes
+        .iter()
+        .map(|class| {
+            json!({
+                "class_hash": class.class_hash,
+                "compiled_class_hash": class.compiled_class_hash,
+            })
+        })
+        .collect();
+    let deployed_contracts: Vec<Value> = state_diff
+        .deployed_contracts
+        .iter()
+        .map(|contract| json!({ "address": contract.address, "class_hash": contract.class_hash }))
+        .collect();
+    let replaced_classes: Vec<Value> = state_diff
+        .replaced_classes
+        .iter()
+        .map(|contract| {
+            json!({ "contract_address": contract.address, "class_hash": contract.class_hash })
+        })
+        .collect();
+    let nonces: Vec<Value> = state_diff
+        .nonces
+        .iter()
+        .map(|(contract, nonce)| json!({ "contract_address": contract, "nonce": nonce }))
+        .collect();
+
+    Ok(json!({
+        "block_hash": header.block_hash,
+        "old_root": header.old_root,
+        "new_root": header.new_root,
+        "state_diff": {
+            "storage_diffs": storage_diffs,
+            "deprecated_declared_classes": state_diff.old_declared_contracts,
+            "declared_classes": declared_classes,
+            "deployed_contracts": deployed_contracts,
+            "replaced_classes": replaced_classes,
+            "nonces": nonces,
+        },
+    }))
+}
+
+fn block_with_tx_hashes(db: &Database, block_number: u64) -> Result<Value, RpcError> {
+    let header = db
+        .get_block_header(block_number)?
+        .ok_or(RpcError::BlockNotFound)?;
+    let transactions: Vec<StarkFelt> = db
+        .get_block_transactions(block_number)?
+        .into_iter()
+        .map(|transaction| transaction.transaction_hash)
+        .collect();
+
+    Ok(json!({
+        "status": "ACCEPTED_ON_L2",
+        "block_hash": header.block_hash,
+        "parent_hash": header.parent_block_hash,
+        "block_number": header.block_number,
+        "new_root": header.new_root,
+        "timestamp": header.timestamp,
+        "sequencer_address": header.sequencer_address.unwrap_or_default(),
+        "l1_gas_price": {
+            "price_in_wei": header.l1_gas_price_wei.unwrap_or_default(),
+            "price_in_fri": header.l1_gas_price_fri.unwrap_or_default(),
+        },
+        "starknet_version": header.starknet_version.unwrap_or_default(),
+        "transactions": transactions,
+    }))
+}
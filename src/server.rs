@@ -0,0 +1,29 @@
+use std::sync::Arc;
+
+use axum::routing::post;
+use axum::Router;
+use tokio::sync::watch;
+
+use crate::db::Database;
+use crate::{rest, rpc};
+
+/// Serve the JSON-RPC API (`POST /`) and the REST API on `address` until
+/// `true` is sent on `shutdown`.
+pub async fn serve(
+    db: Arc<Database>,
+    address: String,
+    mut shutdown: watch::Receiver<bool>,
+) -> std::io::Result<()> {
+    let app = Router::new()
+        .route("/", post(rpc::handle))
+        .merge(rest::router())
+        .with_state(db);
+
+    let listener = tokio::net::TcpListener::bind(&address).await?;
+    log::info!("🌐 Server listening on {}", listener.local_addr()?);
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async move {
+            let _ = shutdown.wait_for(|stop| *stop).await;
+        })
+        .await
+}
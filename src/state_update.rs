@@ -1,7 +1,16 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use starknet_api::hash::StarkFelt;
 use std::collections::HashMap;
 
+use crate::block::Block;
+
+/// Response of `get_state_update?includeBlock=true`.
+#[derive(Deserialize, Debug)]
+pub struct StateUpdateWithBlock {
+    pub block: Block,
+    pub state_update: StateUpdate,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct StateUpdate {
     pub block_hash: StarkFelt,
@@ -10,7 +19,7 @@ pub struct StateUpdate {
     pub state_diff: StateDiff,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct StateDiff {
     pub storage_diffs: HashMap<StarkFelt, Vec<StorageDiff>>,
     pub deployed_contracts: Vec<DeployedContract>,
@@ -18,21 +27,37 @@ pub struct StateDiff {
     pub declared_classes: Vec<DeclaredClass>,
     pub nonces: HashMap<StarkFelt, StarkFelt>,
     pub replaced_classes: Vec<DeployedContract>,
+    /// Classes whose compiled class hash changed, only present in recent blocks.
+    #[serde(default)]
+    pub migrated_compiled_classes: Vec<DeclaredClass>,
 }
 
-#[derive(Deserialize, Debug)]
+impl StateDiff {
+    /// Number of individual updates in the state diff.
+    pub fn len(&self) -> usize {
+        self.storage_diffs.values().map(Vec::len).sum::<usize>()
+            + self.deployed_contracts.len()
+            + self.old_declared_contracts.len()
+            + self.declared_classes.len()
+            + self.nonces.len()
+            + self.replaced_classes.len()
+            + self.migrated_compiled_classes.len()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
 pub struct StorageDiff {
     pub key: StarkFelt,
     pub value: StarkFelt,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct DeployedContract {
     pub address: StarkFelt,
     pub class_hash: StarkFelt,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct DeclaredClass {
     pub class_hash: StarkFelt,
     pub compiled_class_hash: StarkFelt,
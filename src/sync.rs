@@ -0,0 +1,373 @@
+use std::sync::Arc;
+
+use futures::StreamExt;
+use tokio::sync::{mpsc, watch};
+
+use crate::block::Reorg;
+use crate::config::Config;
+use crate::db::{Database, DatabaseError};
+use crate::gateway::{BlockId, GatewayClient, GatewayError};
+use crate::state_update::StateUpdateWithBlock;
+
+/// Fetches state updates from the feeder gateway and applies them to the database.
+pub struct Syncer {
+    db: Arc<Database>,
+    config: Arc<Config>,
+    gateway: Arc<GatewayClient>,
+    shutdown: watch::Receiver<bool>,
+}
+
+impl Syncer {
+    /// Create a syncer stopping as soon as `true` is sent on `shutdown`.
+    pub fn new(db: Arc<Database>, config: Arc<Config>, shutdown: watch::Receiver<bool>) -> Self {
+        Syncer {
+            db,
+            gateway: Arc::new(GatewayClient::new(
+                config.feeder_gateway(),
+                &config.client,
+                config.sync.max_attempts,
+            )),
+            config,
+            shutdown,
+        }
+    }
+
+    /// Sync the configured block range, resuming after the last applied block.
+    pub async fn sync(&self) -> Result<(), String> {
+        let end_block = self.config.sync.end_block;
+
+        // a reorg interrupts `sync_range`, keep going from the common ancestor
+        loop {
+            let start_block = self.next_block()?;
+            if start_block > end_block {
+                log::info!("Already synced up to block {}", end_block);
+                return Ok(());
+            }
+            if *self.shutdown.borrow() {
+                return Ok(());
+            }
+            self.sync_range(start_block, end_block).await?;
+            if self.next_block()? == start_block {
+                // the first block is beyond the tip of the chain
+                return Ok(());
+            }
+        }
+    }
+
+    /// Follow the tip of the chain: poll the feeder gateway for the latest block
+    /// and apply the new blocks as they are produced, until shutdown.
+    ///
+    /// Pending blocks are not applied as they can still change.
+    pub async fn follow(&mut self) -> Result<(), String> {
+        let poll_interval = std::time::Duration::from_millis(self.config.sync.poll_interval_ms);
+
+        while !*self.shutdown.borrow() {
+            match self.fetch_latest_block_number().await {
+                Ok(latest) => {
+                    let start_block = self.next_block()?;
+                    if start_block <= latest {
+                        log::info!(
+                            "🔄 Following tip: syncing block {} to {}",
+                            start_block,
+                            latest
+                        );
+                        match self.sync_range(start_block, latest).await {
+                            // check right away if new blocks were produced meanwhile
+                            Ok(()) if self.next_block()? != start_block => continue,
+                            Ok(()) => {}
+                            Err(e) => log::error!("❌ Sync error: {e}"),
+                        }
+                    }
+                }
+                Err(e) => log::warn!("Cannot fetch latest block: {e}"),
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(poll_interval) => {}
+                _ = self.shutdown.changed() => {}
+            }
+        }
+        log::info!("Follower stopped");
+        Ok(())
+    }
+
+    /// First block to sync: right after the last block fully applied to the database.
+    fn next_block(&self) -> Result<u64, String> {
+        let start_block = self.config.sync.start_block;
+
+        match self
+            .db
+            .get_sync_cursor()
+            .map_err(|e| format!("db error: {e}"))?
+        {
+            Some(cursor) => {
+                if start_block > cursor.block_number + 1 {
+                    log::warn!(
+                        "Start block {} is ahead of the database, resuming from block {} to keep histories contiguous",
+                        start_block,
+                        cursor.block_number + 1
+                    );
+                }
+                Ok(cursor.block_number + 1)
+            }
+            None => Ok(start_block),
+        }
+    }
+
+    /// Sync blocks `start_block..=end_block`.
+    ///
+    /// Stops early, without error, on shutdown, after rolling back a chain
+    /// reorganization or when the database was reverted meanwhile: the caller
+    /// resumes from the new sync cursor.
+    async fn sync_range(&self, start_block: u64, end_block: u64) -> Result<(), String> {
+        let concurrency = self.config.sync.concurrency;
+        let gateway = self.gateway.clone();
+
+        log::info!("Processing block: {} to {}", start_block, end_block);
+
+        // fetch workers: at most `concurrency` requests in flight, yielded in
+        // block order, blocked when `pipeline_buffer` blocks await application
+        let (sender, receiver) = mpsc::channel(self.config.sync.pipeline_buffer);
+        let producer = tokio::spawn(async move {
+            let mut fetches = futures::stream::iter(start_block..=end_block)
+                .map(|number| {
+                    let gateway = gateway.clone();
+                    async move { (number, gateway.get_state_update_with_block(number).await) }
+                })
+                .buffered(concurrency);
+            while let Some(result) = fetches.next().await {
+                if sender.send(result).await.is_err() {
+                    // the applier stopped
+                    break;
+                }
+            }
+        });
+
+        let result = self.apply_fetched(receiver).await;
+        producer.abort();
+        result
+    }
+
+    /// Apply the fetched blocks in order, until the channel is closed or
+    /// shutdown, without waiting for the fetch in flight.
+    async fn apply_fetched(
+        &self,
+        mut receiver: mpsc::Receiver<(u64, Result<StateUpdateWithBlock, GatewayError>)>,
+    ) -> Result<(), String> {
+        let mut metrics = Metrics::new(std::time::Duration::from_secs(
+            self.config.sync.metrics_interval_secs,
+        ));
+        let mut shutdown = self.shutdown.clone();
+
+        loop {
+            let (block_number, result) = tokio::select! {
+                received = receiver.recv() => match received {
+                    Some(received) => received,
+                    None => break,
+                },
+                Ok(_) = shutdown.wait_for(|stop| *stop) => {
+                    log::info!("Sync interrupted while fetching");
+                    return Ok(());
+                }
+            };
+            let update = match result {
+                Ok(update) => update,
+                Err(GatewayError::BlockNotFound(_)) => {
+                    log::info!(
+                        "Block {} not found, reached the tip of the chain",
+                        block_number
+                    );
+                    return Ok(());
+                }
+                Err(e) => return Err(format!("fetch error at block {block_number}: {e}")),
+            };
+            if update.block.block_number != block_number
+                || update.block.block_hash != update.state_update.block_hash
+            {
+                return Err(format!(
+                    "inconsistent block {block_number}: got block {} with hash {}, state update of {}",
+                    update.block.block_number,
+                    update.block.block_hash,
+                    update.state_update.block_hash
+                ));
+            }
+            if *self.shutdown.borrow() {
+                log::info!("Sync interrupted before block {}", block_number);
+                return Ok(());
+            }
+            if !self.extends_database(block_number, &update)? {
+                self.handle_reorg(block_number).await?;
+                return Ok(());
+            }
+
+            let diffs = update.state_update.state_diff.len();
+            if !apply_update(self.db.clone(), block_number, update).await? {
+                // reverted meanwhile, the caller resumes from the sync cursor
+                return Ok(());
+            }
+            metrics.record(block_number, diffs, receiver.len());
+        }
+        Ok(())
+    }
+
+    /// Check that the block is the child of the last block of the database
+    /// and starts from its state.
+    fn extends_database(
+        &self,
+        block_number: u64,
+        update: &StateUpdateWithBlock,
+    ) -> Result<bool, String> {
+        let Some(parent_number) = block_number.checked_sub(1) else {
+            return Ok(true);
+        };
+        match self
+            .db
+            .get_block_header(parent_number)
+            .map_err(|e| format!("db error: {e}"))?
+        {
+            Some(parent) => Ok(parent.block_hash == update.block.parent_block_hash
+                && parent.new_root == update.state_update.old_root),
+            // the parent was synced before headers were stored
+            None => Ok(true),
+        }
+    }
+
+    /// Roll back the database to the last block shared with the canonical chain.
+    ///
+    /// `block_number` is the first block whose parent does not match the database.
+    async fn handle_reorg(&self, block_number: u64) -> Result<(), String> {
+        let max_depth = self.config.sync.max_reorg_depth;
+        let mut orphaned = Vec::new();
+        let mut ancestor = block_number;
+
+        let common_ancestor = loop {
+            ancestor = match ancestor.checked_sub(1) {
+                Some(ancestor) => ancestor,
+                None => return Err("reorg reaches the genesis block".into()),
+            };
+            if orphaned.len() as u64 >= max_depth {
+                return Err(format!("reorg deeper than {} blocks", max_depth));
+            }
+
+            let stored = self
+                .db
+                .get_block_header(ancestor)
+                .map_err(|e| format!("db error: {e}"))?
+                .ok_or(format!("no stored header for block {ancestor}"))?;
+            let canonical = self
+                .gateway
+                .get_block_header(BlockId::Number(ancestor))
+                .await
+                .map_err(|e| format!("fetch error: {e}"))?;
+
+            if canonical.block_hash == stored.block_hash {
+                break ancestor;
+            }
+            orphaned.push((ancestor, stored.block_hash));
+        };
+        if orphaned.is_empty() {
+            // nothing to roll back, the block itself is inconsistent
+            return Err(format!(
+                "block {block_number} does not extend block {common_ancestor} of the canonical chain"
+            ));
+        }
+
+        orphaned.reverse();
+        let reorg = Reorg {
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            detected_at: block_number,
+            common_ancestor,
+            orphaned,
+        };
+        log::warn!(
+            "🔀 Reorg of depth {} detected at block {}, reverting to block {}, orphaned blocks: {:?}",
+            reorg.depth(),
+            block_number,
+            common_ancestor,
+            reorg.orphaned
+        );
+
+        self.db
+            .revert_to(common_ancestor)
+            .map_err(|e| format!("db error: {e}"))?;
+        self.db
+            .insert_reorg(&reorg)
+            .map_err(|e| format!("db error: {e}"))
+    }
+
+    async fn fetch_latest_block_number(&self) -> Result<u64, String> {
+        self.gateway
+            .get_block_header(BlockId::Latest)
+            .await
+            .map(|header| header.block_number)
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Apply a block to the database in a single atomic write.
+///
+/// Returns `false`, without applying it, if the block does not follow the
+/// sync cursor anymore, e.g. after a revert.
+async fn apply_update(
+    db: Arc<Database>,
+    block_number: u64,
+    update: StateUpdateWithBlock,
+) -> Result<bool, String> {
+    let result = tokio::task::spawn_blocking(move || {
+        db.apply_block(block_number, &update.block, &update.state_update)
+    })
+    .await
+    .map_err(|e| format!("apply task error: {e}"))?;
+    match result {
+        Ok(()) => Ok(true),
+        Err(DatabaseError::NonContiguousBlock { last, .. }) => {
+            log::info!(
+                "Database moved to block {} while syncing, dropping block {}",
+                last,
+                block_number
+            );
+            Ok(false)
+        }
+        Err(e) => Err(format!("db error at block {block_number}: {e}")),
+    }
+}
+
+/// Throughput of the applier, logged every interval.
+struct Metrics {
+    interval: std::time::Duration,
+    since: std::time::Instant,
+    blocks: u64,
+    diffs: usize,
+}
+
+impl Metrics {
+    fn new(interval: std::time::Duration) -> Self {
+        Metrics {
+            interval,
+            since: std::time::Instant::now(),
+            blocks: 0,
+            diffs: 0,
+        }
+    }
+
+    fn record(&mut self, block_number: u64, diffs: usize, buffered: usize) {
+        self.blocks += 1;
+        self.diffs += diffs;
+
+        let elapsed = self.since.elapsed();
+        if elapsed >= self.interval {
+            let seconds = elapsed.as_secs_f64();
+            log::info!(
+                "📈 Block {}: {:.1} blocks/s, {:.1} diffs/s, {} blocks buffered",
+                block_number,
+                self.blocks as f64 / seconds,
+                self.diffs as f64 / seconds,
+                buffered
+            );
+            *self = Metrics::new(self.interval);
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use starknet_api::hash::StarkFelt;
+
+/// A transaction, as returned by the feeder gateway.
+///
+/// Fields that do not exist for the transaction type or version are `None`
+/// or empty.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Transaction {
+    pub transaction_hash: StarkFelt,
+    #[serde(rename = "type")]
+    pub r#type: TransactionType,
+    #[serde(default)]
+    pub version: Option<StarkFelt>,
+    /// Account sending the transaction (`DECLARE` and `INVOKE_FUNCTION` v1+).
+    #[serde(default)]
+    pub sender_address: Option<StarkFelt>,
+    /// Deployed contract (`DEPLOY`, `DEPLOY_ACCOUNT`), target of `L1_HANDLER`
+    /// and `INVOKE_FUNCTION` v0.
+    #[serde(default)]
+    pub contract_address: Option<StarkFelt>,
+    #[serde(default)]
+    pub class_hash: Option<StarkFelt>,
+    #[serde(default)]
+    pub compiled_class_hash: Option<StarkFelt>,
+    #[serde(default)]
+    pub entry_point_selector: Option<StarkFelt>,
+    #[serde(default)]
+    pub nonce: Option<StarkFelt>,
+    #[serde(default)]
+    pub max_fee: Option<StarkFelt>,
+    #[serde(default)]
+    pub tip: Option<StarkFelt>,
+    #[serde(default)]
+    pub contract_address_salt: Option<StarkFelt>,
+    #[serde(default)]
+    pub calldata: Vec<StarkFelt>,
+    #[serde(default)]
+    pub constructor_calldata: Vec<StarkFelt>,
+    #[serde(default)]
+    pub signature: Vec<StarkFelt>,
+}
+
+/// Where a transaction is stored: its block and its index in the block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransactionLocation {
+    pub block_number: u64,
+    pub index: u64,
+}
+
+/// An indexed transaction whose nonce disagrees with the nonce history of
+/// its account.
+#[derive(Debug, Clone)]
+pub struct NonceMismatch {
+    pub nonce: StarkFelt,
+    pub location: TransactionLocation,
+    /// Nonce of the account before the block of the transaction.
+    pub nonce_before: Option<StarkFelt>,
+    /// Nonce of the account after the block of the transaction.
+    pub nonce_after: Option<StarkFelt>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum TransactionType {
+    Declare,
+    Deploy,
+    DeployAccount,
+    InvokeFunction,
+    L1Handler,
+}
+
+impl Transaction {
+    /// Account sending the transaction and its nonce, `None` for transactions
+    /// not sent by an account (`DEPLOY`, `L1_HANDLER`, `INVOKE_FUNCTION` v0).
+    pub fn account_nonce(&self) -> Option<(StarkFelt, StarkFelt)> {
+        let account = match self.r#type {
+            TransactionType::Declare | TransactionType::InvokeFunction => self.sender_address?,
+            // the deployed account sends its own deployment
+            TransactionType::DeployAccount => self.contract_address?,
+            TransactionType::Deploy | TransactionType::L1Handler => return None,
+        };
+        Some((account, self.nonce?))
+    }
+}
+
+/// Execution receipt of a transaction.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Receipt {
+    pub transaction_hash: StarkFelt,
+    pub transaction_index: u64,
+    #[serde(default)]
+    pub actual_fee: Option<StarkFelt>,
+    /// `SUCCEEDED` or `REVERTED`, missing before Starknet v0.12.1.
+    #[serde(default)]
+    pub execution_status: Option<String>,
+    #[serde(default)]
+    pub revert_error: Option<String>,
+    #[serde(default)]
+    pub events: Vec<Event>,
+    #[serde(default)]
+    pub l2_to_l1_messages: Vec<L2ToL1Message>,
+    #[serde(default)]
+    pub execution_resources: Option<ExecutionResources>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Event {
+    pub from_address: StarkFelt,
+    pub keys: Vec<StarkFelt>,
+    pub data: Vec<StarkFelt>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct L2ToL1Message {
+    pub from_address: StarkFelt,
+    pub to_address: StarkFelt,
+    pub payload: Vec<StarkFelt>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExecutionResources {
+    pub n_steps: u64,
+    #[serde(default)]
+    pub n_memory_holes: u64,
+    #[serde(default)]
+    pub builtin_instance_counter: HashMap<String, u64>,
+}